@@ -0,0 +1,165 @@
+//! Transparent gzip and zstd compression of text files, chosen by the file extension.
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Write},
+    path::Path,
+};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use zstd::stream::{read::Decoder, write::Encoder};
+
+use crate::output::fail;
+
+/// Codecs accepted by --text_compression.
+pub const CODECS: [&str; 3] = ["none", "gzip", "zstd"];
+
+/// Extension added to the files written with the codec.
+pub fn extension(codec: &str) -> &str {
+    match codec {
+        "gzip" => ".gz",
+        "zstd" => ".zst",
+        _ => "",
+    }
+}
+
+/// Codec of a file, taken from its extension.
+/// ```text
+/// candles.csv.gz => gzip, candles.ndjson.zst => zstd, candles.csv => none
+/// ```
+pub fn codec(file: &str) -> &str {
+    match Path::new(file).extension().map(|extension| extension.to_string_lossy().to_lowercase()) {
+        Some(extension) if extension == "gz" => "gzip",
+        Some(extension) if extension == "zst" || extension == "zstd" => "zstd",
+        _ => "none",
+    }
+}
+
+/// The file name without the compression extension, ie. candles.csv for candles.csv.gz.
+pub fn strip_extension(file: &str) -> &str {
+    match codec(file) {
+        "none" => file,
+        _ => file.rsplit_once('.').map_or(file, |(stem, _)| stem),
+    }
+}
+
+/// Writer that compresses what is written to it as it goes.
+pub enum CompressedWriter<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(Encoder<'static, W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    /// Wraps the writer.
+    /// ```text
+    /// Args:
+    /// writer: W: Where the compressed bytes are written.
+    /// codec: &str: One of CODECS.
+    /// ```
+    pub fn new(writer: W, codec: &str) -> Self {
+        match codec {
+            "gzip" => Self::Gzip(GzEncoder::new(writer, Compression::default())),
+            "zstd" => {
+                Self::Zstd(Encoder::new(writer, 0).unwrap_or_else(|_| fail("Could not start zstd")))
+            }
+            _ => Self::Plain(writer),
+        }
+    }
+
+    /// Writes the end of the compressed stream.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Plain(mut writer) => writer.flush().map(|_| writer),
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(writer) => writer.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(writer) => writer.flush(),
+            Self::Gzip(encoder) => encoder.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Creates a file compressed as its extension says, `-` writes to stdout.
+/// ```text
+/// Appending adds a new gzip member or zstd frame, both are read back as one stream.
+///
+/// Args:
+/// file: &str: Path of the file.
+/// append: bool: Append to the existing file instead of replacing it.
+/// ```
+pub fn create(file: &str, append: bool) -> CompressedWriter<Box<dyn Write>> {
+    let handle: Box<dyn Write> = match file {
+        "-" => Box::new(io::stdout()),
+        _ => Box::new(
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(file)
+                .unwrap_or_else(|_| fail(&format!("Could not create {file}"))),
+        ),
+    };
+    CompressedWriter::new(handle, codec(file))
+}
+
+/// Opens a file and decompresses it as its extension says.
+pub fn open(file: &str) -> Box<dyn Read> {
+    let handle: BufReader<File> = BufReader::new(
+        File::open(file).unwrap_or_else(|_| fail(&format!("Could not open {file}"))),
+    );
+    match codec(file) {
+        "gzip" => Box::new(MultiGzDecoder::new(handle)),
+        "zstd" => Box::new(
+            Decoder::with_buffer(handle)
+                .unwrap_or_else(|_| fail(&format!("Could not open {file}"))),
+        ),
+        _ => Box::new(handle),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    #[test]
+    fn appended_streams_read_back_as_one() {
+        ["gz", "zst"].iter().for_each(|extension| {
+            let file: String = env::temp_dir()
+                .join(format!("crypto-candlesticks-{}.csv.{extension}", std::process::id()))
+                .to_string_lossy()
+                .to_string();
+            ["first\n", "second\n"].iter().enumerate().for_each(|(index, line)| {
+                let mut writer: CompressedWriter<Box<dyn Write>> = create(&file, index > 0);
+                writer.write_all(line.as_bytes()).unwrap();
+                writer.finish().unwrap();
+            });
+            let compressed: Vec<u8> = fs::read(&file).unwrap();
+            let mut text: String = String::new();
+            open(&file).read_to_string(&mut text).unwrap();
+            fs::remove_file(&file).unwrap();
+
+            assert_ne!(compressed, b"first\nsecond\n");
+            assert_eq!(text, "first\nsecond\n");
+        });
+        assert_eq!(strip_extension("candles.ndjson.zst"), "candles.ndjson");
+        assert_eq!(codec("candles.csv"), "none");
+    }
+}
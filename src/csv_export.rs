@@ -0,0 +1,240 @@
+//! CSV output, written page by page, compressed when the file ends in .gz or .zst.
+use std::io::Write;
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use csv::{QuoteStyle, Writer, WriterBuilder};
+
+use crate::{
+    compression::{create, CompressedWriter},
+    exchanges::bitfinex::CandleData,
+    output::fail,
+};
+
+/// Columns accepted by --csv_columns, datetime is written as set by --csv_datetime.
+pub const COLUMNS: [&str; 8] =
+    ["open", "high", "low", "close", "volume", "ticker", "interval", "datetime"];
+/// Formats accepted by --csv_datetime.
+pub const DATETIME_FORMATS: [&str; 3] = ["split", "iso", "epoch_ms"];
+/// Quoting accepted by --csv_quote.
+pub const QUOTE_STYLES: [&str; 4] = ["necessary", "always", "never", "non_numeric"];
+
+/// How the CSV file is written, the defaults match the layout in the README.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvSettings {
+    pub delimiter: u8,
+    pub header: bool,
+    /// One of DATETIME_FORMATS, split writes a Date and a Time column.
+    pub datetime: String,
+    /// Columns in the order they are written, from COLUMNS.
+    pub columns: Vec<String>,
+    /// One of QUOTE_STYLES.
+    pub quote: String,
+}
+
+impl Default for CsvSettings {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            header: true,
+            datetime: "split".to_string(),
+            columns: ["open", "close", "high", "low", "volume", "ticker", "datetime"]
+                .iter()
+                .map(|column| column.to_string())
+                .collect(),
+            quote: "necessary".to_string(),
+        }
+    }
+}
+
+/// Reads the delimiter from the command line, `tab` or `\t` for tabs.
+pub fn parse_delimiter(delimiter: &str) -> u8 {
+    match delimiter {
+        "tab" | "\\t" | "\t" => b'\t',
+        _ if delimiter.len() == 1 && delimiter.is_ascii() => delimiter.as_bytes()[0],
+        _ => fail(&format!("Invalid CSV delimiter: {delimiter}, a single character is expected")),
+    }
+}
+
+/// Reads a comma separated list of columns from the command line.
+pub fn parse_columns(columns: &str) -> Vec<String> {
+    columns
+        .split(',')
+        .map(|column| column.trim().to_lowercase())
+        .inspect(|column| {
+            if !COLUMNS.contains(&column.as_str()) {
+                fail(&format!(
+                    "Unknown CSV column: {column}, supported columns are {}",
+                    COLUMNS.join(", ")
+                ))
+            }
+        })
+        .collect()
+}
+
+fn quote_style(name: &str) -> QuoteStyle {
+    match name {
+        "always" => QuoteStyle::Always,
+        "never" => QuoteStyle::Never,
+        "non_numeric" => QuoteStyle::NonNumeric,
+        _ => QuoteStyle::Necessary,
+    }
+}
+
+/// CSV file that receives the candles as they are downloaded.
+pub struct CsvWriter {
+    writer: Writer<CompressedWriter<Box<dyn Write>>>,
+    settings: CsvSettings,
+}
+
+impl CsvWriter {
+    /// Creates the file and writes the header, appended rows are written without one.
+    /// ```text
+    /// Args:
+    /// csv_file: &str: Path of the file, .csv.gz and .csv.zst are compressed.
+    /// settings: &CsvSettings: Delimiter, header, datetime format, columns and quoting.
+    /// append: bool: Append to the existing file instead of replacing it.
+    /// ```
+    pub fn new(csv_file: &str, settings: &CsvSettings, append: bool) -> Self {
+        let writer: Writer<CompressedWriter<Box<dyn Write>>> = WriterBuilder::new()
+            .delimiter(settings.delimiter)
+            .quote_style(quote_style(&settings.quote))
+            .from_writer(create(csv_file, append));
+        let mut csv_writer: Self = Self { writer, settings: settings.clone() };
+        if settings.header && !append {
+            let header: Vec<&str> = csv_writer
+                .settings
+                .columns
+                .iter()
+                .flat_map(|column| match (column.as_str(), settings.datetime.as_str()) {
+                    ("datetime", "split") => vec!["Date", "Time"],
+                    ("datetime", _) => vec!["Timestamp"],
+                    ("open", _) => vec!["Open"],
+                    ("high", _) => vec!["High"],
+                    ("low", _) => vec!["Low"],
+                    ("close", _) => vec!["Close"],
+                    ("volume", _) => vec!["Volume"],
+                    ("ticker", _) => vec!["Ticker"],
+                    _ => vec!["Interval"],
+                })
+                .collect();
+            csv_writer
+                .writer
+                .write_record(header)
+                .unwrap_or_else(|_| fail("Writing to CSV failed"));
+        }
+        csv_writer
+    }
+
+    /// Appends a page of candles.
+    pub fn write_page(&mut self, page: &CandleData, ticker: &str, interval: &str) {
+        page.candles().for_each(|candle| {
+            let datetime: DateTime<Utc> = Utc
+                .timestamp_millis_opt(candle.timestamp)
+                .single()
+                .expect("Datetime could not be converted from timestamp");
+            let record: Vec<String> = self
+                .settings
+                .columns
+                .iter()
+                .flat_map(|column| match (column.as_str(), self.settings.datetime.as_str()) {
+                    ("datetime", "split") => vec![
+                        datetime.format("%m/%d/%Y").to_string(),
+                        datetime.format("%H:%M:%S").to_string(),
+                    ],
+                    ("datetime", "iso") => {
+                        vec![datetime.to_rfc3339_opts(SecondsFormat::Secs, true)]
+                    }
+                    ("datetime", _) => vec![candle.timestamp.to_string()],
+                    ("open", _) => vec![candle.open.to_string()],
+                    ("high", _) => vec![candle.high.to_string()],
+                    ("low", _) => vec![candle.low.to_string()],
+                    ("close", _) => vec![candle.close.to_string()],
+                    ("volume", _) => vec![candle.volume.to_string()],
+                    ("ticker", _) => vec![ticker.to_string()],
+                    _ => vec![interval.to_string()],
+                })
+                .collect();
+            self.writer.write_record(record).unwrap_or_else(|_| fail("Writing to CSV failed"));
+        });
+    }
+
+    /// Flushes the buffered rows and ends the compressed stream.
+    pub fn close(self) {
+        self.writer
+            .into_inner()
+            .map_err(|_| ())
+            .and_then(|writer| writer.finish().map_err(|_| ()))
+            .unwrap_or_else(|_| fail("Writing to CSV failed"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+    use crate::exchanges::bitfinex::FloatOrInt;
+
+    /// Writes one row to a file named after the test, so the tests can run in parallel.
+    fn write(test: &str, settings: &CsvSettings) -> String {
+        write_twice(test, settings, false)
+    }
+
+    fn write_twice(test: &str, settings: &CsvSettings, twice: bool) -> String {
+        let csv_file: String = env::temp_dir()
+            .join(format!("crypto-candlesticks-{}-{test}.csv", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        [false, true].iter().take(if twice { 2 } else { 1 }).for_each(|append| {
+            let mut writer: CsvWriter = CsvWriter::new(&csv_file, settings, *append);
+            writer.write_page(
+                &CandleData(vec![[
+                    FloatOrInt::Int(1_577_808_000_000),
+                    FloatOrInt::Float(7203.0),
+                    FloatOrInt::Float(7201.0),
+                    FloatOrInt::Float(7203.7),
+                    FloatOrInt::Float(7200.1),
+                    FloatOrInt::Float(9.404174),
+                ]]),
+                "BTC/USD",
+                "1m",
+            );
+            writer.close();
+        });
+        let written: String = fs::read_to_string(&csv_file).unwrap();
+        fs::remove_file(&csv_file).unwrap();
+        written
+    }
+
+    #[test]
+    fn default_layout_matches_readme() {
+        assert_eq!(
+            write("default_layout", &CsvSettings::default()),
+            "Open,Close,High,Low,Volume,Ticker,Date,Time\n\
+             7203,7201,7203.7,7200.1,9.404174,BTC/USD,12/31/2019,16:00:00\n"
+        );
+    }
+
+    #[test]
+    fn follows_settings() {
+        let settings: CsvSettings = CsvSettings {
+            delimiter: b';',
+            header: false,
+            datetime: "epoch_ms".to_string(),
+            columns: parse_columns("datetime,interval,close"),
+            quote: "non_numeric".to_string(),
+        };
+        assert_eq!(write("settings", &settings), "1577808000000;\"1m\";7201\n");
+    }
+
+    #[test]
+    fn appends_rows_without_a_second_header() {
+        let settings: CsvSettings =
+            CsvSettings { datetime: "epoch_ms".to_string(), ..CsvSettings::default() };
+        let row: &str = "7203,7201,7203.7,7200.1,9.404174,BTC/USD,1577808000000\n";
+        assert_eq!(
+            write_twice("append", &settings, true),
+            format!("Open,Close,High,Low,Volume,Ticker,Timestamp\n{row}{row}")
+        );
+    }
+}
@@ -1,10 +1,45 @@
 //! Sqlite database class.
-use std::process;
+use std::{fs, path::Path};
 
-use colorful::Colorful;
-use rusqlite::{params, Connection};
+use chrono::Utc;
+use rusqlite::{params, Connection, OpenFlags, Transaction};
+use serde::Serialize;
+use tracing::info;
 
-use crate::exchanges::bitfinex::CandleData;
+use crate::{
+    exchanges::bitfinex::{Candle, CandleData, FloatOrInt},
+    migrations::{check_version, migrate, schema_version},
+    output::fail,
+    postgresql::PostgresSettings,
+};
+
+/// How the candles are written to SQLite.
+#[derive(Debug, Clone)]
+pub struct DatabaseSettings {
+    /// Single database for every pair and interval, by default each one gets
+    /// its own {ticker}-{interval}.sqlite in the working directory.
+    pub data_base_file: Option<String>,
+    /// Pragmas applied in order when the database is opened, ie. ("synchronous", "OFF").
+    pub pragmas: Vec<(String, String)>,
+    /// Rows written per transaction.
+    pub batch_size: usize,
+    /// Also write the candles to PostgreSQL.
+    pub postgres: Option<PostgresSettings>,
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        Self {
+            data_base_file: None,
+            pragmas: vec![
+                ("journal_mode".to_string(), "WAL".to_string()),
+                ("synchronous".to_string(), "NORMAL".to_string()),
+            ],
+            batch_size: 10000,
+            postgres: None,
+        }
+    }
+}
 
 #[derive(Debug)]
 struct SqlDatabase {
@@ -12,67 +47,483 @@ struct SqlDatabase {
 }
 
 impl SqlDatabase {
-    fn new(data_base_file: String) -> Self {
+    fn new(data_base_file: &str, settings: &DatabaseSettings) -> Self {
+        if let Some(parent) = Path::new(data_base_file).parent() {
+            fs::create_dir_all(parent)
+                .unwrap_or_else(|_| fail(&format!("Could not create {}", parent.display())));
+        }
+        let mut conn: Connection = Connection::open(data_base_file)
+            .unwrap_or_else(|_| fail("Could not write data to the database"));
+        settings.pragmas.iter().for_each(|(pragma, value)| {
+            conn.execute_batch(&format!("PRAGMA {pragma} = {value}"))
+                .unwrap_or_else(|_| fail(&format!("Could not set PRAGMA {pragma} = {value}")));
+        });
+        migrate(&mut conn);
+        Self { conn }
+    }
+
+    /// Opens an existing database without modifying it.
+    fn open_read_only(data_base_file: &str) -> Self {
+        let conn: Connection =
+            Connection::open_with_flags(data_base_file, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .unwrap_or_else(|_| fail(&format!("Could not open {data_base_file}")));
+        check_version(&conn);
+        Self { conn }
+    }
+}
+
+/// Exchange recorded for candles whose source is not known, ie. imported files.
+pub const UNKNOWN_EXCHANGE: &str = "unknown";
+
+/// Provenance of a download, stored in the Download table.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DownloadRecord {
+    pub run_timestamp: i64,
+    pub tool_version: String,
+    pub exchange: String,
+    pub endpoint: String,
+    pub ticker: String,
+    pub interval: String,
+    pub requested_start: i64,
+    pub requested_end: i64,
+    pub actual_start: Option<i64>,
+    pub actual_end: Option<i64>,
+    pub requests: usize,
+    pub candles: usize,
+    pub gaps: usize,
+}
+
+impl DownloadRecord {
+    /// A record for a run starting now with this version of the tool.
+    pub fn new(exchange: &str, endpoint: String, ticker: &str, interval: &str) -> Self {
         Self {
-            conn: Connection::open(data_base_file + ".sqlite").unwrap_or_else(|_| {
-                eprintln!("{}", "Could not write data to the database".red());
-                process::exit(1);
-            }),
+            run_timestamp: Utc::now().timestamp_millis(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            exchange: exchange.to_string(),
+            endpoint,
+            ticker: ticker.to_string(),
+            interval: interval.to_string(),
+            requested_start: 0,
+            requested_end: 0,
+            actual_start: None,
+            actual_end: None,
+            requests: 0,
+            candles: 0,
+            gaps: 0,
         }
     }
 }
 
-/// Sqlite table schema.
-fn create_schema<'a>() -> &'a str {
-    "CREATE TABLE IF NOT EXISTS Candlestick(
-    ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
-    Timestamp REAL,
-    Open REAL,
-    Close REAL,
-    High REAL,
-    Low REAL,
-    Volume REAL,
-    Ticker TEXT,
-    Interval TEXT)"
+/// Inserts a candle or updates it if it was already stored.
+fn upsert_statement<'a>() -> &'a str {
+    "INSERT INTO Candlestick (Timestamp, Open, Close, High, Low, Volume, Ticker, Interval)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+    ON CONFLICT(Ticker, Interval, Timestamp) DO UPDATE SET
+    Open = excluded.Open,
+    Close = excluded.Close,
+    High = excluded.High,
+    Low = excluded.Low,
+    Volume = excluded.Volume"
 }
 
-/// Writes the candlestick data into a SQL table.
+/// Upserts the candles into a migrated database.
 /// ```text
-/// Args:
-/// file_name: &str: Filename for the file
-/// candlestick_info: &[CandleData]: List containing the candlestick information.
-/// ticker: &str: Time interval of the candle.
-/// interval: &str: Time period downloaded.
+/// The candles are written through a cached prepared statement inside one
+/// transaction per batch of rows.
 /// ```
-pub fn insert_candlesticks(candlestick_info: &[CandleData], ticker: &str, interval: &str) {
-    let connection: Connection = SqlDatabase::new(ticker.to_string() + "-" + interval).conn;
-    connection.execute(create_schema(), []).unwrap_or_else(|_| {
-        eprintln!("{}", "Could not write schema to the database".red());
-        process::exit(1);
-    });
-
-    candlestick_info.iter().for_each(|candlestick| {
-        candlestick.0.clone().into_iter().for_each(|candle_data| {
-            connection
-                .execute(
-                    "INSERT INTO Candlestick (Timestamp, Open, Close, High, Low, Volume, Ticker, \
-                     Interval)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-                    params![
-                        candle_data[0],
-                        candle_data[2],
+fn write_candlesticks(
+    connection: &mut Connection,
+    candlestick_info: &[CandleData],
+    ticker: &str,
+    interval: &str,
+    batch_size: usize,
+) {
+    let rows: Vec<&[FloatOrInt; 6]> =
+        candlestick_info.iter().flat_map(|candlestick| candlestick.0.iter()).collect();
+    rows.chunks(batch_size.max(1)).for_each(|batch| {
+        let transaction: Transaction = connection
+            .transaction()
+            .unwrap_or_else(|_| fail("Could not start a database transaction"));
+        {
+            let mut statement = transaction
+                .prepare_cached(upsert_statement())
+                .unwrap_or_else(|_| fail("Could not prepare the insert statement"));
+            batch.iter().for_each(|candle_data| {
+                statement
+                    .execute(params![
+                        candle_data[0].as_i64(),
                         candle_data[1],
+                        candle_data[2],
                         candle_data[3],
                         candle_data[4],
                         candle_data[5],
                         ticker,
                         interval,
-                    ],
+                    ])
+                    .unwrap_or_else(|_| fail("Could not insert data to the table"));
+            });
+        }
+        transaction.commit().unwrap_or_else(|_| fail("Could not commit data to the table"));
+    });
+}
+
+/// File where the candles of a ticker and interval are stored.
+fn data_base_path(ticker: &str, interval: &str, settings: &DatabaseSettings) -> String {
+    settings
+        .data_base_file
+        .clone()
+        .unwrap_or_else(|| ticker.to_string() + "-" + interval + ".sqlite")
+}
+
+/// Writes the candlestick data into a SQL table.
+/// ```text
+/// Args:
+/// candlestick_info: &[CandleData]: List containing the candlestick information.
+/// ticker: &str: Quote + base asset.
+/// interval: &str: Time interval of the candle.
+/// settings: &DatabaseSettings: File, pragmas and batch size.
+///
+/// Returns:
+/// String: Name of the database file.
+/// ```
+pub fn insert_candlesticks(
+    candlestick_info: &[CandleData],
+    ticker: &str,
+    interval: &str,
+    settings: &DatabaseSettings,
+) -> String {
+    let data_base_file: String = data_base_path(ticker, interval, settings);
+    let mut connection: Connection = SqlDatabase::new(&data_base_file, settings).conn;
+    write_candlesticks(&mut connection, candlestick_info, ticker, interval, settings.batch_size);
+    info!(
+        file = %data_base_file,
+        ticker,
+        interval,
+        rows = candlestick_info.iter().map(|candlestick| candlestick.0.len()).sum::<usize>(),
+        "rows written"
+    );
+    data_base_file
+}
+
+/// Adds a download to the history of the database.
+/// ```text
+/// Args:
+/// record: &DownloadRecord: Provenance of the download.
+/// settings: &DatabaseSettings: File, pragmas and batch size.
+///
+/// Returns:
+/// i64: ID of the record, used to update it.
+/// ```
+pub fn record_download(record: &DownloadRecord, settings: &DatabaseSettings) -> i64 {
+    let connection: Connection =
+        SqlDatabase::new(&data_base_path(&record.ticker, &record.interval, settings), settings)
+            .conn;
+    connection
+        .execute(
+            "INSERT INTO Download (RunTimestamp, ToolVersion, Exchange, Endpoint, Ticker, \
+             Interval, RequestedStart, RequestedEnd, ActualStart, ActualEnd, Requests, Candles, \
+             Gaps)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                record.run_timestamp,
+                record.tool_version,
+                record.exchange,
+                record.endpoint,
+                record.ticker,
+                record.interval,
+                record.requested_start,
+                record.requested_end,
+                record.actual_start,
+                record.actual_end,
+                record.requests as i64,
+                record.candles as i64,
+                record.gaps as i64,
+            ],
+        )
+        .unwrap_or_else(|_| fail("Could not record the download"));
+    connection.last_insert_rowid()
+}
+
+/// Updates a download that keeps running, ie. the watch command.
+/// ```text
+/// Args:
+/// id: i64: ID returned by record_download.
+/// record: &DownloadRecord: Provenance of the download so far.
+/// settings: &DatabaseSettings: File, pragmas and batch size.
+/// ```
+pub fn update_download(id: i64, record: &DownloadRecord, settings: &DatabaseSettings) {
+    let connection: Connection =
+        SqlDatabase::new(&data_base_path(&record.ticker, &record.interval, settings), settings)
+            .conn;
+    connection
+        .execute(
+            "UPDATE Download SET RequestedEnd = ?1, ActualStart = ?2, ActualEnd = ?3, \
+             Requests = ?4, Candles = ?5, Gaps = ?6 WHERE ID = ?7",
+            params![
+                record.requested_end,
+                record.actual_start,
+                record.actual_end,
+                record.requests as i64,
+                record.candles as i64,
+                record.gaps as i64,
+                id,
+            ],
+        )
+        .unwrap_or_else(|_| fail("Could not update the download history"));
+}
+
+/// Reads the download history of a database, oldest first.
+pub fn read_downloads(data_base_file: &str) -> Vec<DownloadRecord> {
+    let database: SqlDatabase = SqlDatabase::open_read_only(data_base_file);
+    if schema_version(&database.conn) < 3 {
+        return Vec::new();
+    }
+    let mut statement = database
+        .conn
+        .prepare(
+            "SELECT RunTimestamp, ToolVersion, Exchange, Endpoint, Ticker, Interval, \
+             RequestedStart, RequestedEnd, ActualStart, ActualEnd, Requests, Candles, Gaps
+            FROM Download ORDER BY ID",
+        )
+        .unwrap_or_else(|_| fail("Could not read the Download table"));
+    let rows = statement
+        .query_map([], |row| {
+            Ok(DownloadRecord {
+                run_timestamp: row.get(0)?,
+                tool_version: row.get(1)?,
+                exchange: row.get(2)?,
+                endpoint: row.get(3)?,
+                ticker: row.get(4)?,
+                interval: row.get(5)?,
+                requested_start: row.get(6)?,
+                requested_end: row.get(7)?,
+                actual_start: row.get(8)?,
+                actual_end: row.get(9)?,
+                requests: row.get::<_, i64>(10)? as usize,
+                candles: row.get::<_, i64>(11)? as usize,
+                gaps: row.get::<_, i64>(12)? as usize,
+            })
+        })
+        .unwrap_or_else(|_| fail("Could not read the Download table"));
+    rows.map(|row| row.unwrap_or_else(|_| fail("Could not read a row from the Download table")))
+        .collect()
+}
+
+/// Candles selected by the query command, every filter is optional.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CandleQuery {
+    pub ticker: Option<String>,
+    pub interval: Option<String>,
+    /// First timestamp in ms, inclusive.
+    pub start: Option<i64>,
+    /// Last timestamp in ms, inclusive.
+    pub end: Option<i64>,
+}
+
+/// Reads back every candle stored in a database.
+/// ```text
+/// Args:
+/// data_base_file: &str: Path to the .sqlite file.
+///
+/// Returns:
+/// Vec<(String, String, Vec<Candle>)>: Ticker, interval and its candles sorted by timestamp.
+/// ```
+pub fn read_candlesticks(data_base_file: &str) -> Vec<(String, String, Vec<Candle>)> {
+    query_candlesticks(data_base_file, &CandleQuery::default())
+}
+
+/// Reads the candles matching the query.
+/// ```text
+/// Args:
+/// data_base_file: &str: Path to the .sqlite file.
+/// query: &CandleQuery: Ticker, interval and time range to select.
+///
+/// Returns:
+/// Vec<(String, String, Vec<Candle>)>: Candles grouped by ticker and interval, oldest first.
+/// ```
+pub fn query_candlesticks(
+    data_base_file: &str,
+    query: &CandleQuery,
+) -> Vec<(String, String, Vec<Candle>)> {
+    let connection: Connection = SqlDatabase::open_read_only(data_base_file).conn;
+    select_candlesticks(&connection, query)
+}
+
+fn select_candlesticks(
+    connection: &Connection,
+    query: &CandleQuery,
+) -> Vec<(String, String, Vec<Candle>)> {
+    let mut statement = connection
+        .prepare(
+            "SELECT Ticker, Interval, Timestamp, Open, Close, High, Low, Volume FROM Candlestick
+            WHERE (?1 IS NULL OR Ticker = ?1) AND (?2 IS NULL OR Interval = ?2)
+            AND (?3 IS NULL OR Timestamp >= ?3) AND (?4 IS NULL OR Timestamp <= ?4)
+            ORDER BY Ticker, Interval, Timestamp, ID",
+        )
+        .unwrap_or_else(|_| fail("Could not read the Candlestick table"));
+    let rows = statement
+        .query_map(params![query.ticker, query.interval, query.start, query.end], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                Candle {
+                    timestamp: row.get::<_, f64>(2)? as i64,
+                    open: row.get(3)?,
+                    close: row.get(4)?,
+                    high: row.get(5)?,
+                    low: row.get(6)?,
+                    volume: row.get(7)?,
+                },
+            ))
+        })
+        .unwrap_or_else(|_| fail("Could not read the Candlestick table"));
+
+    let mut series: Vec<(String, String, Vec<Candle>)> = Vec::new();
+    rows.for_each(|row| {
+        let (ticker, interval, candle) =
+            row.unwrap_or_else(|_| fail("Could not read a row from the Candlestick table"));
+        match series.last_mut() {
+            Some((last_ticker, last_interval, candles))
+                if *last_ticker == ticker && *last_interval == interval =>
+            {
+                candles.push(candle)
+            }
+            _ => series.push((ticker, interval, vec![candle])),
+        }
+    });
+    series
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, time::Instant};
+
+    use super::*;
+
+    fn page(close: f64) -> CandleData {
+        CandleData(vec![[
+            FloatOrInt::Int(1_609_459_200_000),
+            FloatOrInt::Float(10.0),
+            FloatOrInt::Float(close),
+            FloatOrInt::Float(12.0),
+            FloatOrInt::Float(9.0),
+            FloatOrInt::Float(1.5),
+        ]])
+    }
+
+    #[test]
+    fn upserts_instead_of_duplicating() {
+        let mut connection: Connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection);
+        write_candlesticks(&mut connection, &[page(11.0)], "BTCUSD", "1m", 1);
+        write_candlesticks(&mut connection, &[page(11.5)], "BTCUSD", "1m", 1);
+        write_candlesticks(&mut connection, &[page(11.5)], "ETHUSD", "1m", 1);
+
+        let (rows, close, timestamp_type): (i64, f64, String) = connection
+            .query_row(
+                "SELECT COUNT(*), MAX(Close), typeof(Timestamp) FROM Candlestick
+                WHERE Ticker = 'BTCUSD'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((rows, close, timestamp_type.as_str()), (1, 11.5, "integer"));
+    }
+
+    #[test]
+    fn records_download_history() {
+        let data_base_file: String = env::temp_dir()
+            .join(format!("crypto-candlesticks-history-{}.sqlite", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let settings: DatabaseSettings =
+            DatabaseSettings { data_base_file: Some(data_base_file.clone()), ..Default::default() };
+        let mut record: DownloadRecord = DownloadRecord {
+            requested_start: 1_609_459_200_000,
+            requested_end: 1_609_545_600_000,
+            requests: 2,
+            ..DownloadRecord::new("Bitfinex", "endpoint".to_string(), "BTCUSD", "1m")
+        };
+        let id: i64 = record_download(&record, &settings);
+        record.requests = 3;
+        record.candles = 1440;
+        record.actual_start = Some(1_609_459_200_000);
+        record.actual_end = Some(1_609_545_540_000);
+        update_download(id, &record, &settings);
+
+        assert_eq!(read_downloads(&data_base_file), vec![record]);
+        ["", "-wal", "-shm"].iter().for_each(|suffix| {
+            let _ = fs::remove_file(format!("{data_base_file}{suffix}"));
+        });
+    }
+
+    #[test]
+    fn selects_candles_by_query() {
+        let mut connection: Connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection);
+        write_candlesticks(&mut connection, &[page(11.0)], "BTCUSD", "1m", 1);
+        write_candlesticks(&mut connection, &[page(11.0)], "BTCUSD", "1h", 1);
+        write_candlesticks(&mut connection, &[page(11.0)], "ETHUSD", "1m", 1);
+
+        let query: CandleQuery = CandleQuery {
+            ticker: Some("BTCUSD".to_string()),
+            interval: Some("1m".to_string()),
+            ..Default::default()
+        };
+        let series = select_candlesticks(&connection, &query);
+        assert_eq!(series.len(), 1);
+        assert_eq!((series[0].0.as_str(), series[0].1.as_str()), ("BTCUSD", "1m"));
+        assert_eq!(series[0].2[0].close, 11.0);
+
+        let query: CandleQuery =
+            CandleQuery { start: Some(1_609_459_200_001), ..Default::default() };
+        assert!(select_candlesticks(&connection, &query).is_empty());
+        assert_eq!(select_candlesticks(&connection, &CandleQuery::default()).len(), 3);
+    }
+
+    /// Run with `cargo test --release million_rows -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn million_rows_load() {
+        let minute: i64 = 60_000;
+        let pages: Vec<CandleData> = (0..1_000_000 / 1440)
+            .map(|day| {
+                CandleData(
+                    (0..1440)
+                        .map(|candle| {
+                            [
+                                FloatOrInt::Int((day * 1440 + candle) * minute),
+                                FloatOrInt::Float(10.5),
+                                FloatOrInt::Float(10.0),
+                                FloatOrInt::Float(11.0),
+                                FloatOrInt::Float(9.5),
+                                FloatOrInt::Float(1.0),
+                            ]
+                        })
+                        .collect(),
                 )
-                .unwrap_or_else(|_| {
-                    eprintln!("{}", "Could not insert data to the table".red());
-                    process::exit(1);
-                });
+            })
+            .collect();
+        let rows: usize = pages.iter().map(|page| page.0.len()).sum();
+        let data_base_file: String =
+            env::temp_dir().join("crypto-candlesticks-bench.sqlite").to_string_lossy().to_string();
+        let settings: DatabaseSettings = DatabaseSettings::default();
+
+        let started: Instant = Instant::now();
+        let mut connection: Connection = SqlDatabase::new(&data_base_file, &settings).conn;
+        write_candlesticks(&mut connection, &pages, "BTCUSD", "1m", settings.batch_size);
+        println!("Inserted {rows} rows in {:?}", started.elapsed());
+
+        let started: Instant = Instant::now();
+        write_candlesticks(&mut connection, &pages, "BTCUSD", "1m", settings.batch_size);
+        println!("Upserted {rows} rows in {:?}", started.elapsed());
+
+        let stored: i64 =
+            connection.query_row("SELECT COUNT(*) FROM Candlestick", [], |row| row.get(0)).unwrap();
+        assert_eq!(stored as usize, rows);
+        drop(connection);
+        ["", "-wal", "-shm"].iter().for_each(|suffix| {
+            let _ = fs::remove_file(format!("{data_base_file}{suffix}"));
         });
-    });
+    }
 }
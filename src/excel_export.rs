@@ -0,0 +1,161 @@
+//! Excel output with numeric and date cells.
+use chrono::{DateTime, NaiveDateTime};
+use rust_xlsxwriter::{Format, Workbook, Worksheet};
+
+use crate::{exchanges::bitfinex::CandleData, output::fail};
+
+/// Columns of every sheet with their width.
+const COLUMNS: [(&str, f64); 8] = [
+    ("open", 14.0),
+    ("high", 14.0),
+    ("low", 14.0),
+    ("close", 14.0),
+    ("volume", 16.0),
+    ("interval", 10.0),
+    ("ticker", 12.0),
+    ("timestamp", 20.0),
+];
+
+/// How the workbook is laid out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExcelSettings {
+    /// One sheet per ticker and interval instead of a single sheet.
+    pub sheet_per_series: bool,
+}
+
+/// Adds a sheet with a frozen header row.
+fn add_sheet(workbook: &mut Workbook, name: &str, header: &Format) {
+    let worksheet: &mut Worksheet = workbook.add_worksheet();
+    worksheet.set_name(name).unwrap_or_else(|_| fail(&format!("Invalid sheet name: {name}")));
+    worksheet.set_freeze_panes(1, 0).unwrap_or_else(|_| fail("Writing to excel failed"));
+    COLUMNS.iter().enumerate().for_each(|(column, (name, width))| {
+        worksheet
+            .write_string_with_format(0, column as u16, *name, header)
+            .and_then(|worksheet| worksheet.set_column_width(column as u16, *width))
+            .unwrap_or_else(|_| fail("Writing to excel failed"));
+    });
+}
+
+/// Name of the sheet of a series.
+/// ```text
+/// Excel compares sheet names ignoring case, BTCUSD 1m and BTCUSD 1M get different names.
+/// ```
+fn sheet_name(ticker: &str, interval: &str, taken: &[String]) -> String {
+    let name: String = format!("{ticker} {interval}");
+    match taken.iter().any(|other| other.eq_ignore_ascii_case(&name)) {
+        true => format!("{name} ({})", taken.len() + 1),
+        false => name,
+    }
+}
+
+/// Converts the data to an excel workbook.
+/// ```text
+/// Args:
+/// excel_file: &str: Filename of the file.
+/// series: &[(String, String, Vec<CandleData>)]: Ticker, interval and its candles.
+/// settings: &ExcelSettings: Single sheet or one sheet per ticker and interval.
+/// ```
+pub fn write_to_excel_file(
+    excel_file: &str,
+    series: &[(String, String, Vec<CandleData>)],
+    settings: &ExcelSettings,
+) {
+    let mut workbook: Workbook = Workbook::new();
+    let header: Format = Format::new().set_bold();
+    let date: Format = Format::new().set_num_format("yyyy-mm-dd hh:mm:ss");
+    let mut sheets: Vec<String> = Vec::new();
+    let mut row: u32 = 0;
+
+    series.iter().for_each(|(ticker, interval, parsed_data)| {
+        if settings.sheet_per_series || sheets.is_empty() {
+            let name: String = match settings.sheet_per_series {
+                true => sheet_name(ticker, interval, &sheets),
+                false => "Crypto-candlesticks".to_string(),
+            };
+            add_sheet(&mut workbook, &name, &header);
+            sheets.push(name);
+            row = 0;
+        }
+        let worksheet: &mut Worksheet = workbook
+            .worksheet_from_index(sheets.len() - 1)
+            .unwrap_or_else(|_| fail("Writing to excel failed"));
+        parsed_data.iter().flat_map(CandleData::candles).for_each(|candle| {
+            row += 1;
+            let datetime: NaiveDateTime = DateTime::from_timestamp_millis(candle.timestamp)
+                .expect("Datetime could not be converted from timestamp")
+                .naive_utc();
+            worksheet
+                .write_number(row, 0, candle.open)
+                .and_then(|worksheet| worksheet.write_number(row, 1, candle.high))
+                .and_then(|worksheet| worksheet.write_number(row, 2, candle.low))
+                .and_then(|worksheet| worksheet.write_number(row, 3, candle.close))
+                .and_then(|worksheet| worksheet.write_number(row, 4, candle.volume))
+                .and_then(|worksheet| worksheet.write_string(row, 5, interval))
+                .and_then(|worksheet| worksheet.write_string(row, 6, ticker))
+                .and_then(|worksheet| worksheet.write_datetime_with_format(row, 7, datetime, &date))
+                .unwrap_or_else(|_| fail("Writing to excel failed"));
+        });
+    });
+    if sheets.is_empty() {
+        add_sheet(&mut workbook, "Crypto-candlesticks", &header);
+    }
+    workbook.save(excel_file).unwrap_or_else(|_| fail("Writing to excel failed"));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use calamine::{open_workbook, Data, Range, Reader, Xlsx};
+
+    use super::*;
+
+    /// BTCUSD of 2020-11-02 as served by Bitfinex: [MTS, OPEN, CLOSE, HIGH, LOW, VOLUME].
+    fn page(timestamp: i64) -> Vec<CandleData> {
+        vec![serde_json::from_str(&format!(
+            "[[{timestamp},13755,13562,13836,13207,6586.08653832]]"
+        ))
+        .unwrap()]
+    }
+
+    #[test]
+    fn writes_typed_cells_per_series() {
+        let excel_file: String = env::temp_dir()
+            .join(format!("crypto-candlesticks-{}.xlsx", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        write_to_excel_file(
+            &excel_file,
+            &[
+                ("BTCUSD".to_string(), "1m".to_string(), page(1_604_275_200_000)),
+                ("BTCUSD".to_string(), "1M".to_string(), page(1_604_361_600_000)),
+            ],
+            &ExcelSettings { sheet_per_series: true },
+        );
+        let mut workbook: Xlsx<_> = open_workbook(&excel_file).unwrap();
+        let sheets: Vec<String> = workbook.sheet_names();
+        let range: Range<Data> = workbook.worksheet_range(&sheets[0]).unwrap();
+        fs::remove_file(&excel_file).unwrap();
+
+        assert_eq!(sheets, vec!["BTCUSD 1m", "BTCUSD 1M (2)"]);
+        let header: Vec<String> =
+            range.rows().next().unwrap().iter().map(Data::to_string).collect();
+        assert_eq!(header, COLUMNS.map(|(name, _)| name.to_string()));
+        let row: &[Data] = range.rows().nth(1).unwrap();
+        assert_eq!(
+            row[..5],
+            [
+                Data::Float(13755.0),
+                Data::Float(13836.0),
+                Data::Float(13207.0),
+                Data::Float(13562.0),
+                Data::Float(6586.08653832)
+            ]
+        );
+        match &row[7] {
+            // Days since 1899-12-30, 2020-11-02 00:00:00.
+            Data::DateTime(datetime) => assert!((datetime.as_f64() - 44137.0).abs() < 1e-9),
+            other => panic!("timestamp is not a date cell: {other:?}"),
+        }
+    }
+}
@@ -1,19 +1,79 @@
 //! Main stuct for the Bitfinex exchange.
 
 use core::fmt;
-use std::{thread, time};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
 
-use colorful::Colorful;
+use chrono::Utc;
 use fmt::{Display, Formatter, Result};
 use reqwest::{blocking, StatusCode};
 use rusqlite::ToSql;
 use serde::Deserialize;
-use simple_excel_writer::{CellValue, ToCellValue};
+use tracing::{debug, error, warn};
+
+use super::{
+    cache::{self, is_closed_window},
+    cassette::{self, Response},
+    Exchange,
+};
+use crate::output::warning;
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(transparent)]
 pub struct CandleData(pub Vec<[FloatOrInt; 6]>);
 
+impl CandleData {
+    /// Parsed view of every candle in the page.
+    pub fn candles(&self) -> impl Iterator<Item = Candle> + '_ {
+        self.0.iter().map(Candle::from)
+    }
+}
+
+/// A single candle with its values converted to numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candle {
+    pub timestamp: i64,
+    pub open: f64,
+    pub close: f64,
+    pub high: f64,
+    pub low: f64,
+    pub volume: f64,
+}
+
+impl From<&[FloatOrInt; 6]> for Candle {
+    fn from(candle_data: &[FloatOrInt; 6]) -> Self {
+        Self {
+            timestamp: candle_data[0].as_i64(),
+            open: candle_data[1].as_f64(),
+            close: candle_data[2].as_f64(),
+            high: candle_data[3].as_f64(),
+            low: candle_data[4].as_f64(),
+            volume: candle_data[5].as_f64(),
+        }
+    }
+}
+
+impl From<&Candle> for [FloatOrInt; 6] {
+    fn from(candle: &Candle) -> Self {
+        [
+            FloatOrInt::Int(candle.timestamp),
+            FloatOrInt::Float(candle.open),
+            FloatOrInt::Float(candle.close),
+            FloatOrInt::Float(candle.high),
+            FloatOrInt::Float(candle.low),
+            FloatOrInt::Float(candle.volume),
+        ]
+    }
+}
+
+impl From<&[Candle]> for CandleData {
+    fn from(candles: &[Candle]) -> Self {
+        Self(candles.iter().map(<[FloatOrInt; 6]>::from).collect())
+    }
+}
+
 #[derive(Deserialize, Debug, Clone, Copy)]
 #[serde(untagged)]
 pub enum FloatOrInt {
@@ -21,20 +81,27 @@ pub enum FloatOrInt {
     Float(f64),
 }
 
-impl Display for FloatOrInt {
-    fn fmt(&self, f: &mut Formatter) -> Result {
+impl FloatOrInt {
+    pub fn as_f64(self) -> f64 {
         match self {
-            FloatOrInt::Int(int) => write!(f, "{}", int),
-            FloatOrInt::Float(float) => write!(f, "{}", float),
+            FloatOrInt::Int(int) => int as f64,
+            FloatOrInt::Float(float) => float,
+        }
+    }
+
+    pub fn as_i64(self) -> i64 {
+        match self {
+            FloatOrInt::Int(int) => int,
+            FloatOrInt::Float(float) => float as i64,
         }
     }
 }
 
-impl ToCellValue for FloatOrInt {
-    fn to_cell_value(&self) -> CellValue {
+impl Display for FloatOrInt {
+    fn fmt(&self, f: &mut Formatter) -> Result {
         match self {
-            FloatOrInt::Int(int) => CellValue::String(int.to_string()),
-            FloatOrInt::Float(float) => CellValue::String(float.to_string()),
+            FloatOrInt::Int(int) => write!(f, "{}", int),
+            FloatOrInt::Float(float) => write!(f, "{}", float),
         }
     }
 }
@@ -48,122 +115,204 @@ impl ToSql for FloatOrInt {
     }
 }
 
+/// Attempts made after a failed request before giving up.
+pub(super) const RETRIES: i8 = 15;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Bitfinex<'a> {
-    api_v1: &'a str,
-    api_v2: &'a str,
+    pub api_v1: &'a str,
+    pub api_v2: &'a str,
+    /// Time a request may take before it is retried.
+    pub timeout: Duration,
+    /// Wait before a failed request is retried.
+    pub retry_delay: Duration,
+    /// Wait between pages to avoid getting rate limited.
+    pub rate_limit: Duration,
+    /// Whether the traffic is recorded or replayed with --cassette, off for a local server.
+    pub cassette: bool,
 }
 
 impl<'a> Bitfinex<'a> {
+    /// Name recorded in the download history.
+    pub const NAME: &'static str = "Bitfinex";
+
     pub fn new() -> Self {
-        Self { api_v1: "https://api.bitfinex.com/v1", api_v2: "https://api.bitfinex.com/v2" }
+        Self {
+            api_v1: "https://api.bitfinex.com/v1",
+            api_v2: "https://api.bitfinex.com/v2",
+            timeout: Duration::from_secs(30),
+            retry_delay: Duration::from_secs(1),
+            rate_limit: Duration::from_secs_f32(1.85),
+            cassette: true,
+        }
     }
 
-    /// Downloads the candlestick data for the given period.
-    /// ```text
-    /// Args:
-    /// start_time: i64: Time in ms on which the data will start.
-    /// ticker: &str: Cryptocurrency pair.
-    /// end_time: i64: Time in ms on which the data will finish.
-    /// time_interval: &str: Interval of the data.
-    ///
-    /// Returns: Option<CandleData>: Returns a list of candle data which can be parsed.
-    /// ```
-    pub fn get_candles(
+    /// Whether the responses are served from the cassette, no waiting is needed.
+    fn is_replaying(self) -> bool {
+        self.cassette && cassette::is_replaying()
+    }
+
+    /// Request of the candles of a window, also the key of the cache.
+    pub fn candles_url(
         self,
         tickers: &str,
         time_interval: &str,
         start_time: i64,
         end_time: i64,
-    ) -> Option<CandleData> {
-        let url: String = format!(
-            "{}/candles/trade:{}:t{}/hist?limit={}&start={}&end={}&sort=-1",
-            self.api_v2,
-            time_interval,
-            tickers.to_uppercase(),
+    ) -> String {
+        format!(
+            "{}?limit={}&start={}&end={}&sort=1",
+            self.candles_endpoint(tickers, time_interval),
             10000, // max allowed by Bitfinex
             start_time,
             end_time
-        );
-        let candle_data_request = blocking::get(&url);
-        match candle_data_request {
-            Ok(data) => {
-                if data.status() == StatusCode::OK {
-                    Some(data.json().unwrap())
-                } else {
-                    self.retry_candles(&url)
-                }
-            }
-            _ if candle_data_request.is_err() => self.retry_candles(&url),
-            _ => None,
-        }
+        )
     }
 
-    /// Calls the exchange and gets all current tickers.
+    /// Sends a GET request, logging the status code and latency.
     /// ```text
-    /// Returns: Option<String>: All available tickers.
+    /// With --cassette replay the recorded response is returned instead, with
+    /// --cassette record the response is kept.
+    ///
+    /// Returns: Option<Response>: None when the exchange could not be reached.
     /// ```
-    pub fn get_symbols(self) -> Option<String> {
-        let url: String = format!("{}{}", self.api_v1, "/symbols");
-        let symbols_request = blocking::get(&url);
-        match symbols_request {
+    fn request(self, url: &str) -> Option<Response> {
+        if let Some(response) = self.cassette.then(|| cassette::replay(url)).flatten() {
+            debug!(url, status = response.status, "response replayed");
+            return Some(response);
+        }
+        debug!(url, "sending request");
+        let started: Instant = Instant::now();
+        let response: reqwest::Result<Response> = blocking::Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .and_then(|client| client.get(url).send())
+            .and_then(|data| {
+                let status: u16 = data.status().as_u16();
+                data.text().map(|body| Response { status, body })
+            });
+        let latency_ms: u64 = started.elapsed().as_millis() as u64;
+        match &response {
             Ok(data) => {
-                if data.status() == StatusCode::OK {
-                    Some(data.text().unwrap())
-                } else {
-                    self.retry_symbol(&url)
+                debug!(url, status = data.status, latency_ms, "response received");
+                if self.cassette {
+                    cassette::record(url, data);
                 }
             }
-            _ if symbols_request.is_err() => self.retry_symbol(&url),
-            _ => None,
+            Err(error) => warn!(url, latency_ms, %error, "request failed"),
         }
+        response.ok()
     }
 
     /// Will retry to download the data in case of an interruption.
-    fn retry_symbol(self, url: &str) -> Option<String> {
+    fn retry(self, url: &str) -> Option<String> {
         let mut counter: i8 = 0;
         loop {
             counter += 1;
-            let retry_symbol_request = blocking::get(url);
-            thread::sleep(time::Duration::from_secs(1));
-            if counter >= 15 {
-                println!("{}", "Cannot connect to Bitfinex, please try again".red())
-            } else if retry_symbol_request.is_ok()
-                && retry_symbol_request
-                    .as_ref()
-                    .expect("Error downloading the data, try again")
-                    .status()
-                    == StatusCode::OK
-            {
-                retry_symbol_request
-                    .expect("Error downloading the data, try again")
-                    .json::<String>()
-                    .expect("Error parsing the data, try again");
+            if !self.is_replaying() {
+                thread::sleep(self.retry_delay);
+            }
+            if counter > RETRIES {
+                error!(url, retries = RETRIES, "giving up on request");
+                warning("Cannot connect to Bitfinex, please try again");
+                return None;
+            }
+            warn!(url, attempt = counter, "retrying request");
+            match self.request(url) {
+                Some(response) if response.status == StatusCode::OK => return Some(response.body),
+                _ => continue,
             }
         }
     }
+}
 
-    /// Will retry to download the data in case of an interruption.
-    fn retry_candles(self, url: &str) -> Option<CandleData> {
-        let mut counter: i8 = 0;
-        loop {
-            counter += 1;
-            let retry_candles_request = blocking::get(url);
-            thread::sleep(time::Duration::from_secs(1));
-            if counter >= 15 {
-                println!("{}", "Cannot connect to Bitfinex, please try again".red())
-            } else if retry_candles_request.is_ok()
-                && retry_candles_request
-                    .as_ref()
-                    .expect("Error downloading the data, try again")
-                    .status()
-                    == StatusCode::OK
-            {
-                retry_candles_request
-                    .expect("Error downloading the data, try again")
-                    .json::<CandleData>()
-                    .expect("Error downloading the data, try again");
+impl Exchange for Bitfinex<'_> {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    /// Endpoint used to download the candles of a ticker.
+    fn candles_endpoint(&self, tickers: &str, time_interval: &str) -> String {
+        format!("{}/candles/trade:{}:t{}/hist", self.api_v2, time_interval, tickers.to_uppercase())
+    }
+
+    /// Candles of a window stored by a previous run, None when the cache is off or misses.
+    fn cached_candles(
+        &self,
+        tickers: &str,
+        time_interval: &str,
+        start_time: i64,
+        end_time: i64,
+    ) -> Option<CandleData> {
+        cache::load(&self.candles_url(tickers, time_interval, start_time, end_time))
+            .and_then(|body| serde_json::from_str(&body).ok())
+    }
+
+    /// Pages whose window is fully closed are cached, the still-forming candle never is.
+    fn get_candles(
+        &self,
+        tickers: &str,
+        time_interval: &str,
+        start_time: i64,
+        end_time: i64,
+    ) -> Option<CandleData> {
+        let url: String = self.candles_url(tickers, time_interval, start_time, end_time);
+        let body: Option<String> = match self.request(&url) {
+            Some(response) if response.status == StatusCode::OK => Some(response.body),
+            _ => self.retry(&url),
+        };
+        let candle_data: Option<CandleData> =
+            body.as_deref().and_then(|body| serde_json::from_str(body).ok());
+        if let (Some(body), Some(_)) = (&body, &candle_data) {
+            if is_closed_window(end_time, time_interval, Utc::now().timestamp_millis()) {
+                cache::store(&url, body);
             }
         }
+        candle_data
+    }
+
+    /// Calls the exchange and gets all current tickers.
+    /// ```text
+    /// Returns: Option<String>: All available tickers.
+    /// ```
+    fn get_symbols(&self) -> Option<String> {
+        let url: String = format!("{}{}", self.api_v1, "/symbols");
+        match self.request(&url) {
+            Some(response) if response.status == StatusCode::OK => Some(response.body),
+            _ => self.retry(&url),
+        }
+    }
+
+    fn rate_limit(&self) -> Duration {
+        match self.is_replaying() {
+            true => Duration::ZERO,
+            false => self.rate_limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_candles_in_the_bitfinex_layout() {
+        // BTCUSD 1D of 2020-11-02 as served by /v2/candles: [MTS, OPEN, CLOSE, HIGH, LOW, VOLUME].
+        let page: CandleData =
+            serde_json::from_str("[[1604275200000,13755,13562,13836,13207,6586.08653832]]")
+                .unwrap();
+        let candle: Candle = page.candles().next().unwrap();
+        assert_eq!(
+            candle,
+            Candle {
+                timestamp: 1_604_275_200_000,
+                open: 13755.0,
+                close: 13562.0,
+                high: 13836.0,
+                low: 13207.0,
+                volume: 6586.08653832,
+            }
+        );
+        assert_eq!(CandleData::from(&[candle][..]).candles().next(), Some(candle));
     }
 }
@@ -0,0 +1,108 @@
+//! On-disk cache of exchange responses, keyed by the request URL.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use tracing::debug;
+
+use crate::{output::fail, symbols::intervals::next_timestamp};
+
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Turns the cache on, responses are stored under the directory.
+pub fn set_cache_dir(cache_dir: &str) {
+    fs::create_dir_all(cache_dir)
+        .unwrap_or_else(|_| fail(&format!("Could not create {cache_dir}")));
+    let _ = CACHE_DIR.set(PathBuf::from(cache_dir));
+}
+
+/// Whether every candle a window can hold is closed, only those pages are cached.
+/// ```text
+/// Args:
+/// end_time: i64: End of the window in ms, the last candle opens at or before it.
+/// interval: &str: Interval of the candles.
+/// now: i64: Current time in ms.
+/// ```
+pub fn is_closed_window(end_time: i64, interval: &str, now: i64) -> bool {
+    next_timestamp(end_time, interval).is_some_and(|close_time| close_time <= now)
+}
+
+/// File of a URL, ie. api.bitfinex.com/v2_candles_trade_1m_tBTCUSD_hist_limit_10000_....json.
+fn cache_file(cache_dir: &Path, url: &str) -> PathBuf {
+    let address: &str = url.split_once("://").map_or(url, |(_, address)| address);
+    let (host, request) = address.split_once('/').unwrap_or((address, ""));
+    let name: String = request
+        .chars()
+        .map(|character| match character {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => character,
+            _ => '_',
+        })
+        .collect();
+    cache_dir.join(host).join(name + ".json")
+}
+
+/// Reads a cached response.
+pub(super) fn load_from(cache_dir: &Path, url: &str) -> Option<String> {
+    fs::read_to_string(cache_file(cache_dir, url)).ok()
+}
+
+/// Stores a response, written to a temporary file first so a cut off run leaves no partial page.
+pub(super) fn store_in(cache_dir: &Path, url: &str, body: &str) {
+    let file: PathBuf = cache_file(cache_dir, url);
+    let temporary: PathBuf = file.with_extension("json.tmp");
+    let stored: std::io::Result<()> = file
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&temporary, body))
+        .and_then(|_| fs::rename(&temporary, &file));
+    if stored.is_err() {
+        debug!(url, "response could not be cached");
+    }
+}
+
+/// Cached response of a URL, None when the cache is off or the URL was never stored.
+pub fn load(url: &str) -> Option<String> {
+    CACHE_DIR.get().and_then(|cache_dir| load_from(cache_dir, url))
+}
+
+/// Stores a response when the cache is on.
+pub fn store(url: &str, body: &str) {
+    if let Some(cache_dir) = CACHE_DIR.get() {
+        store_in(cache_dir, url, body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn stores_responses_by_url() {
+        let cache_dir: PathBuf =
+            env::temp_dir().join(format!("crypto-candlesticks-cache-{}", std::process::id()));
+        let url: &str = "https://api.bitfinex.com/v2/candles/trade:1m:tBTCUSD/hist?limit=10000\
+                         &start=1609459200000&end=1609545600000&sort=1";
+        store_in(&cache_dir, url, "[[1609459200000,2,1,3,0.5,10]]");
+        let file: PathBuf = cache_file(&cache_dir, url);
+        let stored: Option<String> = load_from(&cache_dir, url);
+        let other: Option<String> = load_from(&cache_dir, &url.replace("1m", "5m"));
+        fs::remove_dir_all(&cache_dir).unwrap();
+
+        assert_eq!(
+            file,
+            cache_dir.join("api.bitfinex.com").join(
+                "v2_candles_trade_1m_tBTCUSD_hist_limit_10000_start_1609459200000_end_\
+                 1609545600000_sort_1.json"
+            )
+        );
+        assert_eq!(stored.as_deref(), Some("[[1609459200000,2,1,3,0.5,10]]"));
+        assert_eq!(other, None);
+        // The window ends at a candle that closes a minute later.
+        assert!(is_closed_window(1_609_545_600_000, "1m", 1_609_545_660_000));
+        assert!(!is_closed_window(1_609_545_600_000, "1m", 1_609_545_659_999));
+    }
+}
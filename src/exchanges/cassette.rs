@@ -0,0 +1,143 @@
+//! Records the exchange traffic to fixture files and replays it, so runs work offline.
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::cache::{load_from, store_in};
+use crate::output::fail;
+
+/// Modes accepted by --cassette.
+pub const MODES: [&str; 3] = ["off", "record", "replay"];
+
+/// Status code and body of an exchange response.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Fixtures of a run, a URL requested several times keeps every response in order.
+#[derive(Debug)]
+struct Cassette {
+    replay: bool,
+    dir: PathBuf,
+    /// Responses recorded or replayed so far for each URL.
+    played: Mutex<HashMap<String, usize>>,
+}
+
+static CASSETTE: OnceLock<Option<Cassette>> = OnceLock::new();
+
+fn new_cassette(mode: &str, cassette_dir: &str) -> Option<Cassette> {
+    match mode {
+        "record" | "replay" => Some(Cassette {
+            replay: mode == "replay",
+            dir: PathBuf::from(cassette_dir),
+            played: Mutex::new(HashMap::new()),
+        }),
+        "off" | "" => None,
+        _ => fail(&format!("Invalid cassette mode: {mode}, expected one of {}", MODES.join(", "))),
+    }
+}
+
+/// Records or replays the exchange traffic in the directory.
+pub fn set_cassette(mode: &str, cassette_dir: &str) {
+    let _ = CASSETTE.set(new_cassette(mode, cassette_dir));
+}
+
+/// The cassette set on the command line, or by the `cassette` and `cassette_dir` environment
+/// variables when the exchange is called without it, ie. from the tests.
+fn cassette() -> Option<&'static Cassette> {
+    CASSETTE
+        .get_or_init(|| {
+            new_cassette(
+                &env::var("cassette").unwrap_or_default(),
+                &env::var("cassette_dir").unwrap_or_else(|_| "cassettes".to_string()),
+            )
+        })
+        .as_ref()
+}
+
+/// Whether responses are served from the fixtures, no waiting for the rate limit is needed.
+pub fn is_replaying() -> bool {
+    cassette().is_some_and(|cassette| cassette.replay)
+}
+
+/// Takes the next position of a URL.
+fn next_play(cassette: &Cassette, url: &str) -> usize {
+    let mut played = cassette.played.lock().unwrap_or_else(|error| error.into_inner());
+    let position: &mut usize = played.entry(url.to_string()).or_default();
+    *position += 1;
+    *position - 1
+}
+
+/// Next recorded response of a URL, the last one is repeated once they run out.
+fn play_from(cassette_dir: &Path, url: &str, position: usize) -> Response {
+    let responses: Vec<Response> = load_from(cassette_dir, url)
+        .and_then(|fixture| serde_json::from_str(&fixture).ok())
+        .unwrap_or_default();
+    responses.get(position).or(responses.last()).cloned().unwrap_or_else(|| {
+        fail(&format!("No recorded response for {url}, record it with --cassette record"))
+    })
+}
+
+/// Adds a response to the fixture of a URL, the first one of a run replaces the old fixture.
+fn record_in(cassette_dir: &Path, url: &str, position: usize, response: &Response) {
+    let mut responses: Vec<Response> = match position {
+        0 => Vec::new(),
+        _ => load_from(cassette_dir, url)
+            .and_then(|fixture| serde_json::from_str(&fixture).ok())
+            .unwrap_or_default(),
+    };
+    responses.push(response.clone());
+    let fixture: String = serde_json::to_string_pretty(&responses)
+        .unwrap_or_else(|_| fail(&format!("Could not record {url}")));
+    store_in(cassette_dir, url, &fixture);
+}
+
+/// Recorded response of a URL when replaying.
+/// ```text
+/// Returns:
+/// Option<Response>: None when not replaying, a URL that was never recorded stops the run.
+/// ```
+pub fn replay(url: &str) -> Option<Response> {
+    cassette()
+        .filter(|cassette| cassette.replay)
+        .map(|cassette| play_from(&cassette.dir, url, next_play(cassette, url)))
+}
+
+/// Keeps a response when recording.
+pub fn record(url: &str, response: &Response) {
+    if let Some(cassette) = cassette().filter(|cassette| !cassette.replay) {
+        record_in(&cassette.dir, url, next_play(cassette, url), response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn replays_recorded_responses_in_order() {
+        let cassette_dir: PathBuf =
+            env::temp_dir().join(format!("crypto-candlesticks-cassette-{}", std::process::id()));
+        let url: &str = "https://api.bitfinex.com/v1/symbols";
+        let limited: Response = Response { status: 429, body: "[\"error\"]".to_string() };
+        let symbols: Response = Response { status: 200, body: "[\"btcusd\"]".to_string() };
+        record_in(&cassette_dir, url, 0, &Response { status: 500, body: String::new() });
+        // A new run replaces the fixture.
+        record_in(&cassette_dir, url, 0, &limited);
+        record_in(&cassette_dir, url, 1, &symbols);
+        let played: Vec<Response> =
+            (0..3).map(|position| play_from(&cassette_dir, url, position)).collect();
+        fs::remove_dir_all(&cassette_dir).unwrap();
+
+        assert_eq!(played, vec![limited, symbols.clone(), symbols]);
+    }
+}
@@ -0,0 +1,213 @@
+//! In-process fake of the Bitfinex API for the tests, serving deterministic candles for any range.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use tiny_http::{Request, Response, Server};
+
+use super::bitfinex::Bitfinex;
+use crate::symbols::intervals::interval_to_millis;
+
+/// Symbols listed by the fake `/v1/symbols`.
+const SYMBOLS: &str = "[\"btcusd\",\"ethusd\",\"ltcusd\",\"xrpusd\",\"btceur\",\"ethbtc\"]";
+
+/// Failure served instead of the next response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// 429 with the body Bitfinex sends when rate limited.
+    TooManyRequests,
+    /// 500.
+    ServerError,
+    /// The response is held back longer than this.
+    Timeout(Duration),
+    /// 200 with no candles.
+    EmptyPage,
+    /// 200 with a body that is not JSON.
+    MalformedJson,
+}
+
+/// Deterministic candle of a timestamp, the prices move with the position on the grid.
+/// ```text
+/// [MTS, OPEN, CLOSE, HIGH, LOW, VOLUME], the same layout as Bitfinex.
+/// ```
+pub fn candle(timestamp: i64, step: i64) -> [f64; 6] {
+    let open: f64 = 100.0 + ((timestamp / step) % 50) as f64;
+    [timestamp as f64, open, open + 1.0, open + 2.0, open - 1.0, 1.5]
+}
+
+/// Candles of a `/v2/candles/trade:{interval}:t{ticker}/hist` request, on the interval grid
+/// from start to end and at most `limit` of them.
+fn candles_body(url: &str) -> Option<String> {
+    let (path, query) = url.split_once('?')?;
+    let interval: &str = path.strip_prefix("/v2/candles/trade:")?.split(':').next()?;
+    let step: i64 = interval_to_millis(interval)?;
+    let parameter = |name: &str| -> Option<i64> {
+        query.split('&').find_map(|pair| pair.strip_prefix(&format!("{name}="))?.parse().ok())
+    };
+    let (start, end, limit) = (parameter("start")?, parameter("end")?, parameter("limit")?);
+    let first: i64 = (start + step - 1).div_euclid(step) * step;
+    let candles: Vec<String> = (0..limit)
+        .map(|index| first + index * step)
+        .take_while(|timestamp| *timestamp <= end)
+        .map(|timestamp| {
+            let values: Vec<String> =
+                candle(timestamp, step).iter().map(|value| value.to_string()).collect();
+            format!("[{}]", values.join(","))
+        })
+        .collect();
+    Some(format!("[{}]", candles.join(",")))
+}
+
+/// Answers a request, or the fault queued for it.
+fn respond(request: Request, fault: Option<Fault>) {
+    let url: String = request.url().to_string();
+    let (status, body): (u16, String) = match fault {
+        Some(Fault::TooManyRequests) => (429, "[\"error\",11010,\"ratelimit: error\"]".to_string()),
+        Some(Fault::ServerError) => (500, "[\"error\",10020,\"internal error\"]".to_string()),
+        Some(Fault::EmptyPage) => (200, "[]".to_string()),
+        Some(Fault::MalformedJson) => (200, "[[1609459200000,1,".to_string()),
+        Some(Fault::Timeout(delay)) => {
+            // Answered from its own thread so the retry is not queued behind it.
+            thread::spawn(move || {
+                thread::sleep(delay);
+                let _ = request.respond(Response::from_string("[]"));
+            });
+            return;
+        }
+        None if url == "/v1/symbols" => (200, SYMBOLS.to_string()),
+        None => match candles_body(&url) {
+            Some(body) => (200, body),
+            None => (404, "[\"error\",10020,\"not found\"]".to_string()),
+        },
+    };
+    let _ = request.respond(Response::from_string(body).with_status_code(status));
+}
+
+/// Fake Bitfinex listening on a free local port until it is dropped.
+pub struct MockBitfinex {
+    server: Arc<Server>,
+    handle: Option<JoinHandle<()>>,
+    faults: Arc<Mutex<VecDeque<Fault>>>,
+    requests: Arc<Mutex<Vec<String>>>,
+    api_v1: String,
+    api_v2: String,
+}
+
+impl MockBitfinex {
+    pub fn start() -> Self {
+        let server: Arc<Server> =
+            Arc::new(Server::http("127.0.0.1:0").expect("Could not start the mock server"));
+        let address: String = server.server_addr().to_string();
+        let faults: Arc<Mutex<VecDeque<Fault>>> = Arc::new(Mutex::new(VecDeque::new()));
+        let requests: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let handle: JoinHandle<()> = {
+            let (server, faults, requests) = (server.clone(), faults.clone(), requests.clone());
+            thread::spawn(move || {
+                while let Ok(request) = server.recv() {
+                    requests.lock().unwrap().push(request.url().to_string());
+                    let fault: Option<Fault> = faults.lock().unwrap().pop_front();
+                    respond(request, fault);
+                }
+            })
+        };
+        Self {
+            server,
+            handle: Some(handle),
+            faults,
+            requests,
+            api_v1: format!("http://{address}/v1"),
+            api_v2: format!("http://{address}/v2"),
+        }
+    }
+
+    /// Client of the mock, failed requests are retried without waiting and --cassette is
+    /// left out.
+    pub fn client(&self) -> Bitfinex<'_> {
+        Bitfinex {
+            api_v1: &self.api_v1,
+            api_v2: &self.api_v2,
+            timeout: Duration::from_millis(200),
+            retry_delay: Duration::ZERO,
+            rate_limit: Duration::ZERO,
+            cassette: false,
+        }
+    }
+
+    /// Serves the faults, in order, to the next requests.
+    pub fn fail_next(&self, faults: &[Fault]) {
+        self.faults.lock().unwrap().extend(faults);
+    }
+
+    /// Path and query of every request received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockBitfinex {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            bitfinex::{Candle, CandleData, RETRIES},
+            Exchange,
+        },
+        *,
+    };
+
+    #[test]
+    fn retries_failed_requests_and_reports_bad_pages() {
+        let mock: MockBitfinex = MockBitfinex::start();
+        let bitfinex: Bitfinex = mock.client();
+        let (start, end): (i64, i64) = (1_609_459_200_000, 1_609_462_800_000);
+
+        mock.fail_next(&[Fault::TooManyRequests, Fault::ServerError]);
+        assert!(bitfinex.get_symbols().is_some_and(|symbols| symbols.contains("btcusd")));
+        assert_eq!(mock.requests().len(), 3);
+
+        mock.fail_next(&[Fault::Timeout(Duration::from_secs(1)), Fault::ServerError]);
+        let candles: Option<CandleData> = bitfinex.get_candles("BTCUSD", "1m", start, end);
+        assert_eq!(candles.as_ref().map(|page| page.0.len()), Some(61));
+        assert_eq!(
+            candles.as_ref().and_then(|page| page.candles().next()),
+            Some(Candle {
+                timestamp: start,
+                open: 120.0,
+                close: 121.0,
+                high: 122.0,
+                low: 119.0,
+                volume: 1.5
+            })
+        );
+        assert_eq!(
+            mock.requests().last().map(String::as_str),
+            Some(
+                "/v2/candles/trade:1m:tBTCUSD/hist?limit=10000&start=1609459200000\
+                 &end=1609462800000&sort=1"
+            )
+        );
+
+        mock.fail_next(&[Fault::EmptyPage, Fault::MalformedJson]);
+        assert_eq!(
+            bitfinex.get_candles("BTCUSD", "1m", start, end).map(|page| page.0.len()),
+            Some(0)
+        );
+        assert!(bitfinex.get_candles("BTCUSD", "1m", start, end).is_none());
+
+        let requests: usize = mock.requests().len();
+        mock.fail_next(&[Fault::ServerError; RETRIES as usize + 1]);
+        assert!(bitfinex.get_candles("BTCUSD", "1m", start, end).is_none());
+        assert_eq!(mock.requests().len() - requests, RETRIES as usize + 1);
+    }
+}
@@ -1,3 +1,61 @@
-//! Module for the Bitfinex exchange.
+//! Exchanges the candles are downloaded from.
+
+use std::time::Duration;
+
+use bitfinex::CandleData;
 
 pub mod bitfinex;
+pub mod cache;
+pub mod cassette;
+#[cfg(test)]
+pub mod mock_server;
+pub mod synthetic;
+
+/// Exchanges accepted by --exchange.
+pub const EXCHANGES: [&str; 2] = ["bitfinex", "synthetic"];
+
+/// Source of candles, every page is returned in the Bitfinex layout.
+pub trait Exchange {
+    /// Name recorded in the download history and used in file names.
+    fn name(&self) -> &'static str;
+
+    /// Endpoint the candles of a ticker are downloaded from.
+    fn candles_endpoint(&self, tickers: &str, time_interval: &str) -> String;
+
+    /// Downloads the candlestick data for the given period.
+    /// ```text
+    /// Args:
+    /// tickers: &str: Cryptocurrency pair.
+    /// time_interval: &str: Interval of the data.
+    /// start_time: i64: Time in ms on which the data will start.
+    /// end_time: i64: Time in ms on which the data will finish.
+    ///
+    /// Returns: Option<CandleData>: None when the candles could not be downloaded.
+    /// ```
+    fn get_candles(
+        &self,
+        tickers: &str,
+        time_interval: &str,
+        start_time: i64,
+        end_time: i64,
+    ) -> Option<CandleData>;
+
+    /// Every ticker listed, ie. ["btcusd","ethusd"].
+    fn get_symbols(&self) -> Option<String>;
+
+    /// Candles of a window stored by a previous run, no request is needed.
+    fn cached_candles(
+        &self,
+        _tickers: &str,
+        _time_interval: &str,
+        _start_time: i64,
+        _end_time: i64,
+    ) -> Option<CandleData> {
+        None
+    }
+
+    /// Wait between pages to avoid getting rate limited, none when the traffic is replayed.
+    fn rate_limit(&self) -> Duration {
+        Duration::ZERO
+    }
+}
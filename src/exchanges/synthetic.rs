@@ -0,0 +1,329 @@
+//! Offline exchange generating realistic candles, for demos, benchmarks and tests.
+use std::{collections::HashMap, f64::consts::TAU};
+
+use chrono::{Datelike, TimeZone, Utc};
+use rand::{RngExt, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::StandardNormal;
+
+use super::{
+    bitfinex::{Candle, CandleData},
+    Exchange,
+};
+use crate::symbols::{
+    intervals::{interval_to_millis, next_timestamp},
+    list_of_currency::LIST_OF_CURRENCY,
+};
+
+const MINUTE: i64 = 60_000;
+const DAY: i64 = 86_400_000;
+const MINUTES_PER_DAY: usize = 1440;
+/// The series start on 2010-01-01, no candles are generated before it.
+const ORIGIN: i64 = 1_262_304_000_000;
+/// Candles returned per request, the same as Bitfinex.
+const LIMIT: usize = 10_000;
+/// Assets listed, paired with every currency of LIST_OF_CURRENCY.
+const ASSETS: [&str; 10] = ["BTC", "ETH", "LTC", "XRP", "EOS", "SOL", "ADA", "DOT", "DOGE", "XMR"];
+
+/// Shape of the generated series.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticSettings {
+    /// The same seed always generates the same candles.
+    pub seed: u64,
+    /// Price on 2010-01-01.
+    pub start_price: f64,
+    /// Annual drift, ie. 0.3 for 30%.
+    pub drift: f64,
+    /// Annual volatility, ie. 0.8 for 80%.
+    pub volatility: f64,
+    /// Volume traded per minute on average, busier around 00:00 UTC and on large moves.
+    pub volume: f64,
+    /// Share of candles left out, like intervals without trades.
+    pub gap_rate: f64,
+}
+
+impl Default for SyntheticSettings {
+    fn default() -> Self {
+        Self {
+            seed: 42,
+            start_price: 100.0,
+            drift: 0.3,
+            volatility: 0.8,
+            volume: 2.0,
+            gap_rate: 0.0,
+        }
+    }
+}
+
+/// Prices of a minute, every interval is built from them.
+#[derive(Debug, Clone, Copy)]
+struct Minute {
+    open: f64,
+    close: f64,
+    high: f64,
+    low: f64,
+    volume: f64,
+}
+
+/// Geometric Brownian motion exchange.
+/// ```text
+/// The log price of every day follows the GBM from 2010-01-01, the minutes in between are a
+/// Brownian bridge seeded by the day. A candle only depends on the seed, the ticker and its
+/// time, so any window, page size or interval reads the same series.
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Synthetic {
+    pub settings: SyntheticSettings,
+}
+
+/// FNV-1a, stable across runs and platforms unlike the std hasher.
+fn hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Uniform number in [0, 1) from any value, splitmix64.
+fn unit(value: u64) -> f64 {
+    let mut mixed: u64 = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (mixed ^ (mixed >> 31)) as f64 / u64::MAX as f64
+}
+
+/// Opening time of the first candle at or after the timestamp.
+fn first_open(timestamp: i64, interval: &str) -> Option<i64> {
+    match interval_to_millis(interval) {
+        Some(step) => Some((timestamp + step - 1).div_euclid(step) * step),
+        None => {
+            let date = Utc.timestamp_millis_opt(timestamp).single()?;
+            let month: i64 = Utc
+                .with_ymd_and_hms(date.year(), date.month(), 1, 0, 0, 0)
+                .single()?
+                .timestamp_millis();
+            if month < timestamp {
+                next_timestamp(month, interval)
+            } else {
+                Some(month)
+            }
+        }
+    }
+}
+
+impl Synthetic {
+    pub fn new(settings: SyntheticSettings) -> Self {
+        Self { settings }
+    }
+
+    /// Random numbers of a ticker, one stream per day and one for the daily prices.
+    fn rng(&self, ticker: &str, stream: u64) -> ChaCha8Rng {
+        ChaCha8Rng::seed_from_u64(
+            self.settings.seed ^ hash(ticker).rotate_left(17) ^ stream.wrapping_mul(0x9e37_79b9),
+        )
+    }
+
+    /// Log price at 00:00 UTC of every day since 2010-01-01.
+    fn daily_log_prices(&self, ticker: &str, days: usize) -> Vec<f64> {
+        let SyntheticSettings { start_price, drift, volatility, .. } = self.settings;
+        let step: f64 = 1.0 / 365.0;
+        let mut rng: ChaCha8Rng = self.rng(ticker, u64::MAX);
+        let mut log_price: f64 = start_price.ln();
+        (0..=days)
+            .map(|_| {
+                let current: f64 = log_price;
+                let shock: f64 = rng.sample(StandardNormal);
+                log_price += (drift - volatility * volatility / 2.0) * step
+                    + volatility * step.sqrt() * shock;
+                current
+            })
+            .collect()
+    }
+
+    /// Minutes of a day, a Brownian bridge between the log prices of the day and the next.
+    fn minutes(&self, ticker: &str, day: usize, from: f64, to: f64) -> Vec<Minute> {
+        let SyntheticSettings { volatility, volume, .. } = self.settings;
+        let sigma: f64 = volatility * (1.0 / (365.0 * MINUTES_PER_DAY as f64)).sqrt();
+        let mut rng: ChaCha8Rng = self.rng(ticker, day as u64);
+        let walk: Vec<f64> = (0..MINUTES_PER_DAY)
+            .scan(0.0, |position: &mut f64, _| {
+                *position += sigma * rng.sample::<f64, _>(StandardNormal);
+                Some(*position)
+            })
+            .collect();
+        let end: f64 = walk[MINUTES_PER_DAY - 1];
+        let log_price = |minute: usize| -> f64 {
+            let share: f64 = minute as f64 / MINUTES_PER_DAY as f64;
+            let position: f64 = if minute == 0 { 0.0 } else { walk[minute - 1] };
+            from + share * (to - from) + position - share * end
+        };
+        (0..MINUTES_PER_DAY)
+            .map(|minute| {
+                let (open, close): (f64, f64) =
+                    (log_price(minute).exp(), log_price(minute + 1).exp());
+                let upper_wick: f64 = rng.sample::<f64, _>(StandardNormal).abs() * sigma / 2.0;
+                let lower_wick: f64 = rng.sample::<f64, _>(StandardNormal).abs() * sigma / 2.0;
+                let profile: f64 = 1.0 + 0.5 * (TAU * minute as f64 / MINUTES_PER_DAY as f64).cos();
+                let activity: f64 =
+                    0.5 + 0.6 * (log_price(minute + 1) - log_price(minute)).abs() / sigma;
+                let noise: f64 = (0.5 * rng.sample::<f64, _>(StandardNormal) - 0.125).exp();
+                Minute {
+                    open,
+                    close,
+                    high: open.max(close) * upper_wick.exp(),
+                    low: open.min(close) * (-lower_wick).exp(),
+                    volume: volume * profile * activity * noise,
+                }
+            })
+            .collect()
+    }
+
+    /// Candles of a window, the still-forming candle covers the minutes up to now.
+    /// ```text
+    /// Args:
+    /// ticker: &str: Cryptocurrency pair.
+    /// interval: &str: Interval of the candles.
+    /// start_time: i64: Time in ms on which the data will start.
+    /// end_time: i64: Time in ms on which the data will finish.
+    /// now: i64: Candles opening later are not generated.
+    /// ```
+    pub fn candles(
+        &self,
+        ticker: &str,
+        interval: &str,
+        start_time: i64,
+        end_time: i64,
+        now: i64,
+    ) -> Vec<Candle> {
+        let ticker: String = ticker.to_uppercase();
+        let opens: Vec<i64> =
+            std::iter::successors(first_open(start_time.max(ORIGIN), interval), |open| {
+                next_timestamp(*open, interval)
+            })
+            .take_while(|open| *open <= end_time.min(now))
+            .take(LIMIT)
+            .collect();
+        let Some(last_close) = opens.last().and_then(|open| next_timestamp(*open, interval)) else {
+            return Vec::new();
+        };
+        let last_day: usize = ((last_close.min(now) - ORIGIN) / DAY) as usize;
+        let daily: Vec<f64> = self.daily_log_prices(&ticker, last_day + 1);
+        let mut days: HashMap<usize, Vec<Minute>> = HashMap::new();
+        let mut minute = |index: i64| -> Minute {
+            let day: usize = (index / MINUTES_PER_DAY as i64) as usize;
+            days.entry(day)
+                .or_insert_with(|| self.minutes(&ticker, day, daily[day], daily[day + 1]))
+                [(index % MINUTES_PER_DAY as i64) as usize]
+        };
+        let interval_hash: u64 = hash(interval);
+        opens
+            .into_iter()
+            .filter(|open| {
+                let draw: u64 = self.settings.seed ^ hash(&ticker) ^ interval_hash ^ *open as u64;
+                unit(draw) >= self.settings.gap_rate
+            })
+            .filter_map(|open| {
+                let close: i64 = next_timestamp(open, interval)?.min(now);
+                let first: i64 = (open - ORIGIN) / MINUTE;
+                let minutes: Vec<Minute> =
+                    (first..((close - ORIGIN) / MINUTE).max(first + 1)).map(&mut minute).collect();
+                Some(Candle {
+                    timestamp: open,
+                    open: minutes.first()?.open,
+                    close: minutes.last()?.close,
+                    high: minutes.iter().map(|minute| minute.high).fold(f64::MIN, f64::max),
+                    low: minutes.iter().map(|minute| minute.low).fold(f64::MAX, f64::min),
+                    volume: minutes.iter().map(|minute| minute.volume).sum(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl Exchange for Synthetic {
+    fn name(&self) -> &'static str {
+        "Synthetic"
+    }
+
+    fn candles_endpoint(&self, tickers: &str, time_interval: &str) -> String {
+        let SyntheticSettings { seed, start_price, drift, volatility, volume, gap_rate } =
+            self.settings;
+        format!(
+            "synthetic://candles/trade:{time_interval}:t{}?seed={seed}&price={start_price}\
+             &drift={drift}&volatility={volatility}&volume={volume}&gaps={gap_rate}",
+            tickers.to_uppercase()
+        )
+    }
+
+    fn get_candles(
+        &self,
+        tickers: &str,
+        time_interval: &str,
+        start_time: i64,
+        end_time: i64,
+    ) -> Option<CandleData> {
+        let now: i64 = Utc::now().timestamp_millis();
+        let candles: Vec<Candle> = self.candles(tickers, time_interval, start_time, end_time, now);
+        Some(CandleData::from(candles.as_slice()))
+    }
+
+    fn get_symbols(&self) -> Option<String> {
+        let symbols: Vec<String> = ASSETS
+            .iter()
+            .flat_map(|asset| {
+                LIST_OF_CURRENCY
+                    .iter()
+                    .filter(move |currency| *currency != asset)
+                    .map(move |currency| format!("\"{}{}\"", asset, currency).to_lowercase())
+            })
+            .collect();
+        Some(format!("[{}]", symbols.join(",")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_the_same_series_for_any_window() {
+        let synthetic: Synthetic = Synthetic::new(SyntheticSettings::default());
+        let (start, end): (i64, i64) = (1_609_459_200_000, 1_609_545_600_000);
+        let minutes: Vec<Candle> = synthetic.candles("BTCUSD", "1m", start, end, i64::MAX);
+        let hours: Vec<Candle> = synthetic.candles("btcusd", "1h", start, end, i64::MAX);
+        let paged: Vec<Candle> = [(start, start + 43_199_999), (start + 43_200_000, end)]
+            .iter()
+            .flat_map(|(from, to)| synthetic.candles("BTCUSD", "1m", *from, *to, i64::MAX))
+            .collect();
+        let reseeded: Vec<Candle> =
+            Synthetic::new(SyntheticSettings { seed: 7, ..SyntheticSettings::default() }).candles(
+                "BTCUSD",
+                "1m",
+                start,
+                end,
+                i64::MAX,
+            );
+        let gapped: Vec<Candle> =
+            Synthetic::new(SyntheticSettings { gap_rate: 0.2, ..SyntheticSettings::default() })
+                .candles("BTCUSD", "1m", start, end, i64::MAX);
+
+        assert_eq!(minutes.len(), 1441);
+        assert_eq!(paged, minutes);
+        assert_ne!(reseeded, minutes);
+        assert!(minutes.windows(2).all(|pair| pair[1].timestamp - pair[0].timestamp == MINUTE));
+        assert!(minutes.iter().all(|candle| {
+            candle.low > 0.0
+                && candle.low <= candle.open.min(candle.close)
+                && candle.high >= candle.open.max(candle.close)
+                && candle.volume >= 0.0
+        }));
+        // An hour holds the same prices as its minutes.
+        let first_hour: &[Candle] = &minutes[..60];
+        assert_eq!(hours[0].open, first_hour[0].open);
+        assert_eq!(hours[0].close, first_hour[59].close);
+        assert_eq!(hours[0].high, first_hour.iter().map(|c| c.high).fold(f64::MIN, f64::max));
+        assert!((hours[0].volume - first_hour.iter().map(|c| c.volume).sum::<f64>()).abs() < 1e-9);
+        // Gaps leave candles out without moving the others.
+        assert!(gapped.len() > 1000 && gapped.len() < 1300);
+        assert!(gapped.iter().all(|candle| minutes.contains(candle)));
+    }
+}
@@ -0,0 +1,214 @@
+//! Output directory, file name templates and what happens to files that already exist.
+use std::{fs, path::Path};
+
+use chrono::{TimeZone, Utc};
+
+use crate::output::fail;
+
+/// Placeholders accepted by --filename_template.
+pub const PLACEHOLDERS: [&str; 7] =
+    ["exchange", "symbol", "base", "interval", "start", "end", "run_date"];
+/// Policies accepted by --if_exists.
+pub const EXISTING_FILE_POLICIES: [&str; 3] = ["overwrite", "append", "skip"];
+
+/// Where the files of a download are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileNameSettings {
+    /// Directory prepended to every generated file name.
+    pub output_dir: Option<String>,
+    /// File name without extension, by default {symbol}{base}-{interval}-{run_date}
+    /// and {symbol}{base}-{interval} for the SQLite database.
+    pub template: Option<String>,
+    /// One of EXISTING_FILE_POLICIES.
+    pub if_exists: String,
+}
+
+impl Default for FileNameSettings {
+    fn default() -> Self {
+        Self { output_dir: None, template: None, if_exists: "overwrite".to_string() }
+    }
+}
+
+/// Values of the placeholders of a download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateValues {
+    pub exchange: String,
+    pub symbol: String,
+    pub base: String,
+    pub interval: String,
+    /// Requested start and end as YYYY-MM-DD.
+    pub start: String,
+    pub end: String,
+    pub run_date: String,
+}
+
+impl TemplateValues {
+    /// Values of a download started now.
+    /// ```text
+    /// Args:
+    /// exchange: &str: Name of the exchange, written in lowercase.
+    /// symbol: &str: Symbol, ie. BTC.
+    /// base: &str: Base currency, ie. USD.
+    /// interval: &str: Period downloaded.
+    /// start: i64: Requested start in ms.
+    /// end: i64: Requested end in ms.
+    /// ```
+    pub fn new(
+        exchange: &str,
+        symbol: &str,
+        base: &str,
+        interval: &str,
+        start: i64,
+        end: i64,
+    ) -> Self {
+        let date = |timestamp: i64| {
+            Utc.timestamp_millis_opt(timestamp)
+                .single()
+                .expect("Datetime could not be converted from timestamp")
+                .format("%Y-%m-%d")
+                .to_string()
+        };
+        Self {
+            exchange: exchange.to_lowercase(),
+            symbol: symbol.to_string(),
+            base: base.to_string(),
+            interval: interval.to_string(),
+            start: date(start),
+            end: date(end),
+            run_date: Utc::now().format("%Y-%m-%d").to_string(),
+        }
+    }
+
+    fn value(&self, placeholder: &str) -> &str {
+        match placeholder {
+            "exchange" => &self.exchange,
+            "symbol" => &self.symbol,
+            "base" => &self.base,
+            "interval" => &self.interval,
+            "start" => &self.start,
+            "end" => &self.end,
+            "run_date" => &self.run_date,
+            _ => fail(&format!(
+                "Unknown placeholder in the file name template: {{{placeholder}}}, supported \
+                 placeholders are {}",
+                PLACEHOLDERS.map(|placeholder| format!("{{{placeholder}}}")).join(", ")
+            )),
+        }
+    }
+}
+
+/// Replaces the placeholders of the template.
+/// ```text
+/// Args:
+/// template: &str: ie. exchange={exchange}/symbol={symbol}{base}/{interval}-{start}.
+/// values: &TemplateValues: Values of the download.
+///
+/// Returns:
+/// String: The rendered file name.
+/// ```
+pub fn render(template: &str, values: &TemplateValues) -> String {
+    let mut rendered: String = String::new();
+    let mut rest: &str = template;
+    while let Some(open) = rest.find('{') {
+        let close: usize = rest[open..]
+            .find('}')
+            .unwrap_or_else(|| fail(&format!("Unclosed placeholder in the template: {template}")));
+        rendered += &rest[..open];
+        rendered += values.value(&rest[open + 1..open + close]);
+        rest = &rest[open + close + 1..];
+    }
+    rendered + rest
+}
+
+/// Creates the directories of a file that is about to be written.
+pub fn create_parent_dir(file: &str) {
+    if let Some(parent) = Path::new(file).parent().filter(|parent| parent != &Path::new("")) {
+        fs::create_dir_all(parent)
+            .unwrap_or_else(|_| fail(&format!("Could not create {}", parent.display())));
+    }
+}
+
+impl FileNameSettings {
+    /// Path of an exported file, nothing is created until a sink opens it.
+    /// ```text
+    /// Args:
+    /// values: &TemplateValues: Values of the download.
+    /// extension: &str: ie. .xlsx.
+    /// ```
+    pub fn path(&self, values: &TemplateValues, extension: &str) -> String {
+        let template: &str =
+            self.template.as_deref().unwrap_or("{symbol}{base}-{interval}-{run_date}");
+        self.in_output_dir(render(template, values) + extension)
+    }
+
+    /// Path of the SQLite database, one per ticker and interval unless templated.
+    pub fn data_base_path(&self, values: &TemplateValues) -> String {
+        let template: &str = self.template.as_deref().unwrap_or("{symbol}{base}-{interval}");
+        self.in_output_dir(render(template, values) + ".sqlite")
+    }
+
+    fn in_output_dir(&self, file: String) -> String {
+        match &self.output_dir {
+            Some(output_dir) => Path::new(output_dir).join(file).to_string_lossy().to_string(),
+            None => file,
+        }
+    }
+
+    /// Whether an existing file is left untouched.
+    pub fn skips(&self, file: &str) -> bool {
+        self.if_exists == "skip" && file != "-" && Path::new(file).exists()
+    }
+
+    /// Whether an existing file is appended to instead of replaced.
+    pub fn appends(&self, file: &str) -> bool {
+        self.if_exists == "append" && file != "-" && Path::new(file).exists()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn renders_templates_into_the_output_dir() {
+        let values: TemplateValues = TemplateValues::new(
+            "Bitfinex",
+            "BTC",
+            "USD",
+            "1m",
+            1_609_459_200_000,
+            1_609_545_599_999,
+        );
+        let output_dir: String = env::temp_dir()
+            .join(format!("crypto-candlesticks-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let settings: FileNameSettings = FileNameSettings {
+            output_dir: Some(output_dir.clone()),
+            template: Some(
+                "exchange={exchange}/{symbol}{base}/{interval}_{start}_{end}".to_string(),
+            ),
+            ..FileNameSettings::default()
+        };
+        let file: String = settings.path(&values, ".csv");
+        let created_early: bool = Path::new(&output_dir).exists();
+        create_parent_dir(&file);
+        let created: bool = Path::new(&output_dir).join("exchange=bitfinex/BTCUSD").is_dir();
+        fs::remove_dir_all(&output_dir).unwrap();
+
+        assert_eq!(
+            file,
+            Path::new(&output_dir)
+                .join("exchange=bitfinex/BTCUSD/1m_2021-01-01_2021-01-01.csv")
+                .to_string_lossy()
+        );
+        assert!(!created_early && created);
+        assert_eq!(
+            FileNameSettings::default().data_base_path(&values),
+            "BTCUSD-1m.sqlite".to_string()
+        );
+        assert_eq!(render("{symbol}-{run_date}", &values), format!("BTC-{}", values.run_date));
+    }
+}
@@ -1,167 +1,194 @@
 //! The Crypto candlesticks engine.
-use std::{process, thread, time};
+use std::thread;
 
-use chrono::{TimeZone, Utc};
-use colorful::{core::color_string::CString, Colorful};
-use simple_excel_writer::{blank, row, CellValue, Row, Sheet, SheetWriter, Workbook};
-use time::Duration;
+use serde_json::json;
+use tracing::debug;
 
 use crate::{
-    database::insert_candlesticks,
-    exchanges::bitfinex::{Bitfinex, CandleData},
-    text_console::{setup_table, write_to_column},
+    csv_export::CsvSettings,
+    database::DatabaseSettings,
+    excel_export::ExcelSettings,
+    exchanges::{
+        bitfinex::{Candle, CandleData},
+        Exchange,
+    },
+    file_names::{FileNameSettings, TemplateValues},
+    json_export::JsonSettings,
+    output::{self, fail, info, success, warning},
+    parquet_export::ParquetSettings,
+    sink::{open_sinks, Download, Series, Sink},
+    symbols,
+    text_console::Progress,
+    validation::{handle_report, validate_candles, ValidationReport, ValidationSettings},
 };
 
-/// Avoid getting rate limited by Bitfinex.
-const RATE_LIMIT: f32 = 1.85;
 /// Slice of data to be downloaded.
-const STEP_SIZE: i64 = 86400000;
+pub const STEP_SIZE: i64 = 86400000;
 
 /// Calls the exchange for the data and extends it into a list.
 /// ```text
 /// Args:
+/// exchange: &dyn Exchange: Exchange the candles are downloaded from.
 /// ticker: &str: Ticker to download the data.
 /// mut start_time: i64: Time in ms on which the data will start.
 /// end_time: i64: Time in ms on which the data will finish.
 /// interval: &str: Period downloaded.
 /// step_size: i64: The size step for each call. Defaults to _STEP_SIZE.
+/// on_page: impl FnMut(&CandleData): Called with every page as soon as it arrives.
 ///
 /// Returns:
 /// Vec<CandleData>: A vector of FloatOrInt.
 /// ```
-fn get_candles(
+pub fn get_candles(
+    exchange: &dyn Exchange,
     ticker: &str,
     mut start_time: i64,
     end_time: i64,
     interval: &str,
     step_size: i64,
+    mut on_page: impl FnMut(&CandleData),
 ) -> Vec<CandleData> {
     let mut candle_data: Vec<CandleData> = Vec::new();
     let message: String = format!("Downloading {} data for {} interval...", ticker, interval);
-    let bitfinex: Bitfinex = Bitfinex::new();
-    println!("{}", message.yellow());
+    let total_requests: u64 = ((end_time - start_time).max(0) / step_size + 1) as u64;
+    let mut progress: Progress = Progress::new(ticker, interval, total_requests);
+    info(&message);
+    tracing::info!(ticker, interval, start_time, end_time, total_requests, "download started");
 
     while start_time <= end_time {
         let period: i64 = start_time + step_size;
+        let cached: Option<CandleData> =
+            exchange.cached_candles(ticker, interval, start_time, period);
+        let from_cache: bool = cached.is_some();
         let candlestick: Option<CandleData> =
-            bitfinex.get_candles(ticker, interval, start_time, period);
+            cached.or_else(|| exchange.get_candles(ticker, interval, start_time, period));
         if candlestick.is_none() {
-            let panic_message: &str =
-                "Data could not be downloaded ❌, please verify your connection and try again";
-            eprintln!("{}", panic_message.red());
-            process::exit(1)
+            fail("Data could not be downloaded ❌, please verify your connection and try again")
         }
 
-        write_to_column(ticker, interval, candle_data.clone(), setup_table());
-        candle_data.push(candlestick.unwrap_or_else(|| {
-            eprintln!("{}", "Could not append to datalist".red());
-            process::exit(1);
-        }));
+        let candlestick: CandleData =
+            candlestick.unwrap_or_else(|| fail("Could not append to datalist"));
+        debug!(
+            ticker,
+            interval,
+            start_time,
+            from_cache,
+            candles = candlestick.0.len(),
+            "page downloaded"
+        );
+        progress.update(&candlestick);
+        on_page(&candlestick);
+        candle_data.push(candlestick);
         start_time = period;
-        thread::sleep(Duration::from_secs_f32(RATE_LIMIT));
+        if !from_cache {
+            thread::sleep(exchange.rate_limit());
+        }
     }
+    progress.finish();
     candle_data
 }
 
-/// Converts the data to an excel sheet.
-/// ```text
-/// Args:
-/// filename: String: Filename of the file.
-/// interval: &str: Time interval of the data.
-/// parsed_data: Vec<CandleData>: A Vector of CandleData struct.
-/// ticker: &str: Quote + base asset.
-/// ```
-fn write_to_excel_file(
-    filename: String,
-    interval: &str,
-    parsed_data: Vec<CandleData>,
-    ticker: &str,
-) {
-    let mut workbook: Workbook =
-        Workbook::create(&(filename + "-" + &Utc::now().format("%Y-%m-%d").to_string() + ".xlsx"));
-    let mut worksheet: Sheet = workbook.create_sheet("Crypto-candlesticks");
-    workbook
-        .write_sheet(
-            &mut worksheet,
-            |sheet_writer: &mut SheetWriter| -> Result<(), std::io::Error> {
-                sheet_writer.append_row(row![
-                    "open",
-                    "high",
-                    "low",
-                    "close",
-                    "volume",
-                    "interval",
-                    "ticker",
-                    "timestamp"
-                ])?;
-                parsed_data.into_iter().for_each(|candlestick| {
-                    candlestick.0.into_iter().for_each(|candle_data| {
-                        let (timestamp, open, close, high, low, volume) = (
-                            &candle_data[0],
-                            &candle_data[2],
-                            &candle_data[1],
-                            &candle_data[3],
-                            &candle_data[4],
-                            &candle_data[5],
-                        );
-                        let datetime: String = Utc
-                            .timestamp_millis(
-                                timestamp
-                                    .to_string()
-                                    .parse()
-                                    .expect("Datetime could not be converted from timestamp"),
-                            )
-                            .to_string();
-                        sheet_writer
-                            .append_row(row![
-                                *open, *close, *high, *low, *volume, interval, ticker, datetime
-                            ])
-                            .unwrap_or_else(|_| {
-                                eprintln!("{}", "Writing to excel failed".red());
-                                process::exit(1);
-                            });
-                    });
-                });
-                sheet_writer.append_row(row![blank!(1), blank!(1), blank!(1)])
-            },
-        )
-        .unwrap_or_else(|_| {
-            eprintln!("{}", "Writing to excel failed".red());
-            process::exit(1);
-        });
-    workbook.close().unwrap_or_else(|_| {
-        eprintln!("{}", "Writing to excel failed".red());
-        process::exit(1);
-    });
+/// Everything that decides what a download writes.
+#[derive(Debug, Clone)]
+pub struct DownloadSettings {
+    pub validation: ValidationSettings,
+    /// Outputs written, from FORMATS.
+    pub formats: Vec<String>,
+    pub database: DatabaseSettings,
+    pub excel: ExcelSettings,
+    /// Output directory, file names and what happens to existing files.
+    pub files: FileNameSettings,
+    pub parquet: ParquetSettings,
+    pub csv: CsvSettings,
+    pub json: JsonSettings,
+    /// Compression of the CSV and JSON files, one of CODECS.
+    pub text_compression: String,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        Self {
+            validation: ValidationSettings::default(),
+            formats: vec!["sqlite".to_string(), "xlsx".to_string()],
+            database: DatabaseSettings::default(),
+            excel: ExcelSettings::default(),
+            files: FileNameSettings::default(),
+            parquet: ParquetSettings::default(),
+            csv: CsvSettings::default(),
+            json: JsonSettings::default(),
+            text_compression: "none".to_string(),
+        }
+    }
 }
 
 /// Function for handling the OHLC response and conversion.
-pub fn get_data(symbol: &str, base_currency: &str, interval: &str, time_start: i64, time_end: i64) {
-    println!("{}", "Welcome, let's download your data".green());
-    let ticker: String = format!("{}{}", symbol, base_currency);
+pub fn get_data(
+    exchange: &dyn Exchange,
+    symbol: &str,
+    base_currency: &str,
+    interval: &str,
+    time_start: i64,
+    time_end: i64,
+    settings: &DownloadSettings,
+) {
+    let validation_settings: &ValidationSettings = &settings.validation;
+    success("Welcome, let's download your data");
+    let ticker: String = symbols::ticker(symbol, base_currency);
+    let series: Series = Series {
+        ticker: ticker.clone(),
+        pair: format!("{}/{}", symbol, base_currency).to_uppercase(),
+        interval: interval.to_string(),
+        exchange: exchange.name().to_string(),
+        endpoint: exchange.candles_endpoint(&ticker, interval),
+    };
+    let values: TemplateValues =
+        TemplateValues::new(exchange.name(), symbol, base_currency, interval, time_start, time_end);
+    let mut sinks: Vec<Box<dyn Sink>> = open_sinks(&series, &values, settings);
+    if sinks.is_empty() {
+        warning("Every output file already exists, nothing to download");
+        return;
+    }
     let candle_stick_data: Vec<CandleData> =
-        get_candles(&ticker, time_start, time_end, interval, STEP_SIZE);
-    let output: String = ticker.clone() + "-" + interval;
-
-    let download_success_message: [CString; 2] =
-        ["Data download completed! 🚀".green(), "Processing data...".yellow()];
-    download_success_message.iter().for_each(|success_message| {
-        println!("{}", success_message);
-    });
-
-    insert_candlesticks(&candle_stick_data, &ticker, interval);
-    write_to_excel_file(output, interval, candle_stick_data, &ticker);
-
-    let success_exit_messages: [CString; 4] = [
-        "Writing to database completed! 🚀🚀".green(),
-        "Writing to Excel...".yellow(),
-        "Writing to Excel completed! 🚀🚀🚀".green(),
-        "----------------------".green(),
-    ];
-    success_exit_messages.iter().for_each(|failure_message| {
-        println!("{failure_message}");
-    });
-    println!("{}", buy_me_a_coffee());
+        get_candles(exchange, &ticker, time_start, time_end, interval, STEP_SIZE, |page| {
+            sinks.iter_mut().for_each(|sink| sink.write_page(page));
+        });
+
+    success("Data download completed! 🚀");
+    info("Processing data...");
+
+    let candles: Vec<Candle> = candle_stick_data.iter().flat_map(CandleData::candles).collect();
+    let report: ValidationReport = validate_candles(&ticker, interval, &candles);
+    if validation_settings.strict && !report.is_valid() {
+        // What was streamed during the download is removed, existing files are left as they were.
+        sinks.drain(..).for_each(|sink| sink.discard());
+    }
+    handle_report(&report, validation_settings);
+    let download: Download = Download {
+        data: &candle_stick_data,
+        report: &report,
+        requested_start: time_start,
+        requested_end: time_end,
+    };
+    let files: Vec<String> = sinks.into_iter().filter_map(|sink| sink.finish(&download)).collect();
+
+    if output::is_json() {
+        output::event(
+            "summary",
+            &json!({
+                "ticker": ticker,
+                "interval": interval,
+                "files": files,
+                "candles": report.candles,
+                "first_timestamp": report.first_timestamp,
+                "last_timestamp": report.last_timestamp,
+                "gaps": report.gaps,
+                "issues": report.issues.len(),
+            }),
+        );
+    } else {
+        success("----------------------");
+        output::print_line(buy_me_a_coffee());
+    }
 }
 
 /// Why not?
@@ -177,3 +204,67 @@ Consider supporting your developers
 ETH: 0x06Acb31587a96808158BdEd07e53668d8ce94cFE
 "
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+    use crate::{
+        database::{query_candlesticks, CandleQuery},
+        exchanges::mock_server::{Fault, MockBitfinex},
+    };
+
+    #[test]
+    fn downloads_every_page() {
+        let mock: MockBitfinex = MockBitfinex::start();
+        let start: i64 = 1_609_459_201_000;
+        let mut pages: usize = 0;
+        mock.fail_next(&[Fault::TooManyRequests]);
+
+        let data: Vec<CandleData> = get_candles(
+            &mock.client(),
+            "BTCUSD",
+            start,
+            start + 3 * STEP_SIZE,
+            "1h",
+            STEP_SIZE,
+            |_| pages += 1,
+        );
+        let candles: Vec<Candle> = data.iter().flat_map(CandleData::candles).collect();
+        let report: ValidationReport = validate_candles("BTCUSD", "1h", &candles);
+
+        assert_eq!((pages, data.len(), mock.requests().len()), (4, 4, 5));
+        assert_eq!(candles.len(), 96);
+        assert_eq!(report.first_timestamp, Some(1_609_462_800_000));
+        assert!(report.is_valid() && report.gaps == 0);
+        assert!(candles.iter().all(|candle| candle.close == candle.open + 1.0));
+    }
+
+    #[test]
+    fn stores_lowercase_pairs_under_the_uppercase_ticker() {
+        let mock: MockBitfinex = MockBitfinex::start();
+        let data_base_file: String = env::temp_dir()
+            .join(format!("crypto-candlesticks-ticker-{}.sqlite", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let settings: DownloadSettings = DownloadSettings {
+            formats: vec!["sqlite".to_string()],
+            database: DatabaseSettings {
+                data_base_file: Some(data_base_file.clone()),
+                ..DatabaseSettings::default()
+            },
+            ..DownloadSettings::default()
+        };
+        let start: i64 = 1_609_459_200_000;
+        get_data(&mock.client(), "btc", "usd", "1h", start, start + STEP_SIZE / 2, &settings);
+        let query: CandleQuery =
+            CandleQuery { ticker: Some("BTCUSD".to_string()), ..CandleQuery::default() };
+        let series: Vec<(String, String, Vec<Candle>)> =
+            query_candlesticks(&data_base_file, &query);
+        fs::remove_file(&data_base_file).unwrap();
+
+        assert_eq!(series.len(), 1);
+        assert_eq!((series[0].0.as_str(), series[0].2.len()), ("BTCUSD", 25));
+    }
+}
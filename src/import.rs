@@ -0,0 +1,658 @@
+//! Imports candle files written by the Python package and by this tool into the database.
+use std::{io::Read, path::Path};
+
+use calamine::{open_workbook_auto, Data, Reader, Sheets};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use csv::ReaderBuilder;
+use serde_json::json;
+
+use crate::{
+    compression::{open, strip_extension},
+    database::{
+        insert_candlesticks, record_download, DatabaseSettings, DownloadRecord, UNKNOWN_EXCHANGE,
+    },
+    exchanges::bitfinex::{Candle, CandleData},
+    output::{self, fail, success, warning},
+    postgresql::insert_candlesticks_postgres,
+    symbols::intervals::INTERVALS,
+    validation::{handle_report, validate_candles, ValidationReport, ValidationSettings},
+};
+
+/// Layouts accepted by --layout.
+/// ```text
+/// header: columns are found by their name, see FIELDS.
+/// legacy: .xlsx written before typed workbooks, its open, high, low and close
+///         columns hold the close, open, high and low.
+/// auto:   legacy for .xlsx files with text cells in that header, header otherwise.
+/// ```
+pub const LAYOUTS: [&str; 3] = ["auto", "header", "legacy"];
+/// Fields a column can be mapped to with --map.
+pub const FIELDS: [&str; 11] = [
+    "timestamp",
+    "date",
+    "time",
+    "open",
+    "high",
+    "low",
+    "close",
+    "volume",
+    "ticker",
+    "interval",
+    "exchange",
+];
+
+/// Header of the workbooks written before typed workbooks.
+const LEGACY_HEADER: [&str; 8] =
+    ["open", "high", "low", "close", "volume", "interval", "ticker", "timestamp"];
+
+/// How the files are read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportSettings {
+    /// One of LAYOUTS.
+    pub layout: String,
+    /// Field and the column it is read from, ie. ("timestamp", "MTS").
+    pub columns: Vec<(String, String)>,
+    /// Ticker of files without a ticker column, taken from the file name otherwise.
+    pub ticker: Option<String>,
+    /// Interval of files without an interval column, taken from the file name otherwise.
+    pub interval: Option<String>,
+    /// Exchange of files without an exchange column, recorded as unknown otherwise.
+    pub exchange: Option<String>,
+    /// CSV delimiter, guessed from the header when not set.
+    pub delimiter: Option<u8>,
+    /// Abort before writing if any file fails validation.
+    pub strict: bool,
+}
+
+impl Default for ImportSettings {
+    fn default() -> Self {
+        Self {
+            layout: "auto".to_string(),
+            columns: Vec::new(),
+            ticker: None,
+            interval: None,
+            exchange: None,
+            delimiter: None,
+            strict: false,
+        }
+    }
+}
+
+/// Reads a comma separated list of FIELD=COLUMN from the command line.
+pub fn parse_mapping(columns: &str) -> Vec<(String, String)> {
+    columns
+        .split(',')
+        .map(|mapping| match mapping.split_once('=') {
+            Some((field, column)) if FIELDS.contains(&field.trim().to_lowercase().as_str()) => {
+                (field.trim().to_lowercase(), column.trim().to_string())
+            }
+            _ => fail(&format!(
+                "Invalid column mapping: {mapping}, expected FIELD=COLUMN with a field from {}",
+                FIELDS.join(", ")
+            )),
+        })
+        .collect()
+}
+
+/// A cell of a CSV or Excel file.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Text(String),
+    Number(f64),
+    /// Days since 1899-12-30.
+    Date(f64),
+    Empty,
+}
+
+impl Value {
+    fn text(&self) -> String {
+        match self {
+            Value::Text(text) => text.trim().to_string(),
+            Value::Number(number) | Value::Date(number) => number.to_string(),
+            Value::Empty => String::new(),
+        }
+    }
+
+    fn number(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            Value::Text(text) => text.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Time in ms, from epoch seconds or ms, Excel dates or text.
+    fn timestamp(&self) -> Option<i64> {
+        const EXCEL_EPOCH_DAYS: f64 = 25569.0;
+        const DAY: f64 = 86_400_000.0;
+        match self {
+            Value::Date(days) => Some(((days - EXCEL_EPOCH_DAYS) * DAY).round() as i64),
+            Value::Number(number) if *number < 1e6 => {
+                Some(((number - EXCEL_EPOCH_DAYS) * DAY).round() as i64)
+            }
+            Value::Number(number) if *number < 1e11 => Some((number * 1000.0).round() as i64),
+            Value::Number(number) => Some(number.round() as i64),
+            Value::Text(text) => match text.trim().parse::<f64>() {
+                Ok(number) => Value::Number(number).timestamp(),
+                Err(_) => parse_datetime(text.trim()),
+            },
+            Value::Empty => None,
+        }
+    }
+}
+
+/// Parses the date formats written by the Python package and by this tool.
+fn parse_datetime(text: &str) -> Option<i64> {
+    const DATETIME_FORMATS: [&str; 5] = [
+        "%Y-%m-%d %H:%M:%S UTC",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%m/%d/%Y %H:%M:%S",
+        "%d/%m/%Y %H:%M:%S",
+    ];
+    const DATE_FORMATS: [&str; 2] = ["%Y-%m-%d", "%m/%d/%Y"];
+    DateTime::parse_from_rfc3339(text)
+        .map(|datetime| datetime.timestamp_millis())
+        .ok()
+        .or_else(|| {
+            DATETIME_FORMATS.iter().find_map(|format| {
+                NaiveDateTime::parse_from_str(text, format)
+                    .ok()
+                    .map(|datetime| datetime.and_utc().timestamp_millis())
+            })
+        })
+        .or_else(|| {
+            DATE_FORMATS.iter().find_map(|format| {
+                NaiveDate::parse_from_str(text, format).ok().and_then(|date| {
+                    date.and_hms_opt(0, 0, 0).map(|datetime| datetime.and_utc().timestamp_millis())
+                })
+            })
+        })
+}
+
+/// Rows of a CSV file, the delimiter is guessed from the header unless set.
+fn read_csv(file: &str, delimiter: Option<u8>) -> Vec<Vec<Value>> {
+    let mut text: String = String::new();
+    open(file)
+        .read_to_string(&mut text)
+        .unwrap_or_else(|_| fail(&format!("Could not read {file}")));
+    let header: &str = text.lines().next().unwrap_or_default();
+    let delimiter: u8 = delimiter.unwrap_or_else(|| {
+        [b',', b';', b'\t', b'|']
+            .into_iter()
+            .max_by_key(|delimiter| header.matches(*delimiter as char).count())
+            .unwrap_or(b',')
+    });
+    ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes())
+        .records()
+        .map(|record| {
+            record
+                .unwrap_or_else(|_| fail(&format!("Could not read {file}")))
+                .iter()
+                .map(|cell| match cell.trim() {
+                    "" => Value::Empty,
+                    cell => Value::Text(cell.to_string()),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Rows of every sheet of a workbook.
+fn read_xlsx(file: &str) -> Vec<Vec<Vec<Value>>> {
+    let mut workbook: Sheets<_> =
+        open_workbook_auto(file).unwrap_or_else(|_| fail(&format!("Could not open {file}")));
+    workbook
+        .worksheets()
+        .into_iter()
+        .map(|(_, range)| {
+            range
+                .rows()
+                .map(|row| {
+                    row.iter()
+                        .map(|cell| match cell {
+                            Data::Int(int) => Value::Number(*int as f64),
+                            Data::Float(float) => Value::Number(*float),
+                            Data::DateTime(datetime) => Value::Date(datetime.as_f64()),
+                            Data::String(text) | Data::DateTimeIso(text) => {
+                                Value::Text(text.clone())
+                            }
+                            _ => Value::Empty,
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Ticker and interval in a file name, ie. BTCUSD-1D-2021-04-14 15-07-48.xlsx.
+fn from_file_name(file: &str) -> (Option<String>, Option<String>) {
+    let name: String = Path::new(strip_extension(file))
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut parts = name.split('-');
+    let ticker: Option<String> = parts
+        .next()
+        .filter(|ticker| ticker.len() >= 6 && ticker.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(str::to_uppercase);
+    let interval: Option<String> = parts
+        .next()
+        .map(|interval| interval.split('.').next().unwrap_or_default().to_string())
+        .filter(|interval| INTERVALS.contains(&interval.as_str()));
+    (ticker, interval)
+}
+
+/// Position of every field in the rows.
+#[derive(Debug, Default)]
+struct Columns {
+    timestamp: Option<usize>,
+    date: Option<usize>,
+    time: Option<usize>,
+    open: Option<usize>,
+    high: Option<usize>,
+    low: Option<usize>,
+    close: Option<usize>,
+    volume: Option<usize>,
+    ticker: Option<usize>,
+    interval: Option<usize>,
+    exchange: Option<usize>,
+}
+
+impl Columns {
+    /// Legacy workbooks hold the close, open, high and low under open, high, low and close.
+    fn legacy() -> Self {
+        Self {
+            timestamp: Some(7),
+            open: Some(1),
+            high: Some(2),
+            low: Some(3),
+            close: Some(0),
+            volume: Some(4),
+            ticker: Some(6),
+            interval: Some(5),
+            ..Self::default()
+        }
+    }
+
+    /// Finds the columns by name, --map first and the usual names otherwise.
+    fn from_header(header: &[String], settings: &ImportSettings) -> Self {
+        let find = |field: &str, names: &[&str]| -> Option<usize> {
+            let mapped: Option<&String> = settings
+                .columns
+                .iter()
+                .find(|(mapped_field, _)| mapped_field == field)
+                .map(|(_, column)| column);
+            match mapped {
+                Some(column) => Some(
+                    header.iter().position(|name| name == &column.to_lowercase()).unwrap_or_else(
+                        || fail(&format!("Column {column} mapped to {field} was not found")),
+                    ),
+                ),
+                None => names.iter().find_map(|name| header.iter().position(|other| other == name)),
+            }
+        };
+        Self {
+            timestamp: find("timestamp", &["timestamp", "ts", "mts", "datetime", "open_time"]),
+            date: find("date", &["date"]),
+            time: find("time", &["time"]),
+            open: find("open", &["open"]),
+            high: find("high", &["high"]),
+            low: find("low", &["low"]),
+            close: find("close", &["close"]),
+            volume: find("volume", &["volume"]),
+            ticker: find("ticker", &["ticker", "pair", "symbol"]),
+            interval: find("interval", &["interval"]),
+            exchange: find("exchange", &["exchange"]),
+        }
+    }
+}
+
+/// Candles of one sheet or CSV file, grouped by exchange, ticker and interval.
+fn candles_from_rows(
+    file: &str,
+    rows: &[Vec<Value>],
+    is_xlsx: bool,
+    settings: &ImportSettings,
+) -> Vec<(String, String, String, Vec<Candle>)> {
+    let Some((header, rows)) = rows.split_first() else {
+        return Vec::new();
+    };
+    let header: Vec<String> = header.iter().map(|name| name.text().to_lowercase()).collect();
+    let legacy: bool = match settings.layout.as_str() {
+        "legacy" => true,
+        "header" => false,
+        _ => {
+            is_xlsx
+                && header == LEGACY_HEADER
+                && rows.first().is_some_and(|row| matches!(row.first(), Some(Value::Text(_))))
+        }
+    };
+    let columns: Columns =
+        if legacy { Columns::legacy() } else { Columns::from_header(&header, settings) };
+    let (file_ticker, file_interval) = from_file_name(file);
+    let required = |column: Option<usize>, field: &str| -> usize {
+        column.unwrap_or_else(|| {
+            fail(&format!("{file} has no {field} column, map one with --map {field}=COLUMN"))
+        })
+    };
+    let (open, high, low, close, volume) = (
+        required(columns.open, "open"),
+        required(columns.high, "high"),
+        required(columns.low, "low"),
+        required(columns.close, "close"),
+        required(columns.volume, "volume"),
+    );
+    if columns.timestamp.is_none() && columns.date.is_none() {
+        fail(&format!("{file} has no timestamp column, map one with --map timestamp=COLUMN"));
+    }
+
+    let mut series: Vec<(String, String, String, Vec<Candle>)> = Vec::new();
+    rows.iter()
+        .enumerate()
+        .filter(|(_, row)| row.iter().any(|cell| cell != &Value::Empty))
+        .for_each(|(index, row)| {
+            let cell = |column: Option<usize>| -> Value {
+                column.and_then(|column| row.get(column).cloned()).unwrap_or(Value::Empty)
+            };
+            let invalid = |field: &str| -> ! {
+                fail(&format!("{file}: row {} has an invalid {field}", index + 2))
+            };
+            let number = |column: usize, field: &str| -> f64 {
+                cell(Some(column)).number().unwrap_or_else(|| invalid(field))
+            };
+            let timestamp: i64 = match (columns.timestamp, columns.date, columns.time) {
+                (Some(column), _, _) => cell(Some(column)).timestamp(),
+                (None, date, Some(time)) => match (cell(date), cell(Some(time))) {
+                    (Value::Date(days), Value::Date(fraction)) => {
+                        Value::Date(days.floor() + fraction.fract()).timestamp()
+                    }
+                    (date, time) => parse_datetime(&format!("{} {}", date.text(), time.text())),
+                },
+                (None, date, None) => cell(date).timestamp(),
+            }
+            .unwrap_or_else(|| invalid("timestamp"));
+            let ticker: String = match cell(columns.ticker) {
+                Value::Empty => settings.ticker.clone().or_else(|| file_ticker.clone()),
+                ticker => Some(ticker.text()),
+            }
+            .map(|ticker| ticker.replace('/', "").to_uppercase())
+            .unwrap_or_else(|| fail(&format!("{file} has no ticker, set it with --pair")));
+            let interval: String = match cell(columns.interval) {
+                Value::Empty => settings.interval.clone().or_else(|| file_interval.clone()),
+                interval => Some(interval.text()),
+            }
+            .unwrap_or_else(|| fail(&format!("{file} has no interval, set it with --interval")));
+            let exchange: String = match cell(columns.exchange) {
+                Value::Empty => settings.exchange.clone(),
+                exchange => Some(exchange.text()),
+            }
+            .unwrap_or_else(|| UNKNOWN_EXCHANGE.to_string());
+            let candle: Candle = Candle {
+                timestamp,
+                open: number(open, "open"),
+                close: number(close, "close"),
+                high: number(high, "high"),
+                low: number(low, "low"),
+                volume: number(volume, "volume"),
+            };
+            match series.iter_mut().find(|(other_exchange, other_ticker, other_interval, _)| {
+                other_exchange == &exchange
+                    && other_ticker == &ticker
+                    && other_interval == &interval
+            }) {
+                Some((_, _, _, candles)) => candles.push(candle),
+                None => series.push((exchange, ticker, interval, vec![candle])),
+            }
+        });
+    series.iter_mut().for_each(|(_, _, _, candles)| candles.sort_by_key(|candle| candle.timestamp));
+    series
+}
+
+/// Reads the candles of a CSV or Excel file.
+/// ```text
+/// Args:
+/// file: &str: .csv, .csv.gz, .csv.zst, .tsv, .xlsx or .xls file.
+/// settings: &ImportSettings: Layout, column mapping, exchange, ticker and interval.
+///
+/// Returns:
+/// Vec<(String, String, String, Vec<Candle>)>: Exchange, ticker, interval and candles sorted
+///                                             by time.
+/// ```
+pub fn read_candles(
+    file: &str,
+    settings: &ImportSettings,
+) -> Vec<(String, String, String, Vec<Candle>)> {
+    let extension: String = Path::new(strip_extension(file))
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "xlsx" | "xlsm" | "xls" => read_xlsx(file)
+            .iter()
+            .flat_map(|rows| candles_from_rows(file, rows, true, settings))
+            .collect(),
+        "csv" | "tsv" | "txt" => candles_from_rows(
+            file,
+            &read_csv(file, settings.delimiter.or((extension == "tsv").then_some(b'\t'))),
+            false,
+            settings,
+        ),
+        _ => fail(&format!("Unsupported file: {file}, CSV and Excel files can be imported")),
+    }
+}
+
+/// Runs the `import` command.
+/// ```text
+/// Every file is read and validated before anything is written, the candles are upserted
+/// and each series is recorded in the download history.
+///
+/// Args:
+/// files: &[&str]: Files to import.
+/// settings: &ImportSettings: Layout, column mapping, exchange, ticker, interval and strictness.
+/// database_settings: &DatabaseSettings: Database the candles are written to.
+/// ```
+pub fn run_import(files: &[&str], settings: &ImportSettings, database_settings: &DatabaseSettings) {
+    let imported: Vec<(&str, String, String, String, Vec<Candle>)> = files
+        .iter()
+        .flat_map(|file| {
+            read_candles(file, settings).into_iter().map(|(exchange, ticker, interval, candles)| {
+                (*file, exchange, ticker, interval, candles)
+            })
+        })
+        .collect();
+    if imported.is_empty() {
+        warning("No candles found to import");
+        return;
+    }
+    let reports: Vec<ValidationReport> = imported
+        .iter()
+        .map(|(_, _, ticker, interval, candles)| validate_candles(ticker, interval, candles))
+        .collect();
+    let validation_settings: ValidationSettings =
+        ValidationSettings { strict: settings.strict, ..ValidationSettings::default() };
+    reports.iter().for_each(|report| handle_report(report, &validation_settings));
+
+    imported.iter().zip(&reports).for_each(
+        |((file, exchange, ticker, interval, candles), report)| {
+            let data_base_file: String = insert_candlesticks(
+                &[CandleData::from(candles.as_slice())],
+                ticker,
+                interval,
+                database_settings,
+            );
+            record_download(
+                &DownloadRecord {
+                    requested_start: report.first_timestamp.unwrap_or_default(),
+                    requested_end: report.last_timestamp.unwrap_or_default(),
+                    actual_start: report.first_timestamp,
+                    actual_end: report.last_timestamp,
+                    requests: 0,
+                    candles: report.candles,
+                    gaps: report.gaps,
+                    ..DownloadRecord::new(exchange, file.to_string(), ticker, interval)
+                },
+                database_settings,
+            );
+            if let Some(postgres_settings) = &database_settings.postgres {
+                insert_candlesticks_postgres(
+                    &[CandleData::from(candles.as_slice())],
+                    exchange,
+                    ticker,
+                    interval,
+                    postgres_settings,
+                );
+            }
+            success(&format!(
+                "Imported {} {ticker} {interval} candles from {file} into {data_base_file}",
+                candles.len()
+            ));
+            output::event(
+                "imported",
+                &json!({
+                    "file": file,
+                    "database": data_base_file,
+                    "exchange": exchange,
+                    "ticker": ticker,
+                    "interval": interval,
+                    "candles": candles.len(),
+                }),
+            );
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use chrono::Utc;
+    use rust_xlsxwriter::{Workbook, Worksheet};
+
+    use super::*;
+    use crate::{
+        csv_export::{CsvSettings, CsvWriter},
+        excel_export::{write_to_excel_file, ExcelSettings},
+        exchanges::{mock_server::MockBitfinex, Exchange},
+    };
+
+    #[test]
+    fn reads_legacy_workbooks() {
+        let file: String =
+            format!("{}/examples/BTCUSD-1D-2021-04-14 15-07-48.xlsx", env!("CARGO_MANIFEST_DIR"));
+        let series: Vec<(String, String, String, Vec<Candle>)> =
+            read_candles(&file, &ImportSettings::default());
+
+        assert_eq!(series.len(), 1);
+        let (exchange, ticker, interval, candles) = &series[0];
+        assert_eq!(
+            (exchange.as_str(), ticker.as_str(), interval.as_str(), candles.len()),
+            ("unknown", "BTCUSD", "1D", 62)
+        );
+        assert_eq!(
+            candles[0],
+            Candle {
+                timestamp: 1_604_275_200_000,
+                open: 13755.0,
+                close: 13562.0,
+                high: 13836.0,
+                low: 13207.0,
+                volume: 6586.08653832,
+            }
+        );
+        assert!(validate_candles(ticker, interval, candles).is_valid());
+    }
+
+    #[test]
+    fn reads_csv_and_typed_workbooks_back() {
+        let candle: Candle = Candle {
+            timestamp: 1_577_808_000_000,
+            open: 7203.0,
+            close: 7201.0,
+            high: 7203.7,
+            low: 7200.1,
+            volume: 9.404174,
+        };
+        let data: Vec<CandleData> = vec![CandleData::from([candle].as_slice())];
+        let directory: String = env::temp_dir()
+            .join(format!("crypto-candlesticks-import-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        fs::create_dir_all(&directory).unwrap();
+        let csv_file: String = format!("{directory}/BTCUSD-1m-2020-01-01.csv.gz");
+        let mut writer: CsvWriter = CsvWriter::new(&csv_file, &CsvSettings::default(), false);
+        writer.write_page(&data[0], "BTC/USD", "1m");
+        writer.close();
+        let excel_file: String = format!("{directory}/candles.xlsx");
+        write_to_excel_file(
+            &excel_file,
+            &[("ETHUSD".to_string(), "1h".to_string(), data)],
+            &ExcelSettings::default(),
+        );
+
+        let from_csv = read_candles(&csv_file, &ImportSettings::default());
+        let from_excel = read_candles(
+            &excel_file,
+            &ImportSettings { exchange: Some("Bitfinex".to_string()), ..ImportSettings::default() },
+        );
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(
+            from_csv,
+            vec![("unknown".to_string(), "BTCUSD".to_string(), "1m".to_string(), vec![candle])]
+        );
+        assert_eq!(
+            from_excel,
+            vec![("Bitfinex".to_string(), "ETHUSD".to_string(), "1h".to_string(), vec![candle])]
+        );
+    }
+
+    #[test]
+    fn imports_legacy_workbooks_like_the_download() {
+        let mock: MockBitfinex = MockBitfinex::start();
+        let page: CandleData = mock
+            .client()
+            .get_candles("BTCUSD", "1h", 1_609_459_200_000, 1_609_542_000_000)
+            .unwrap();
+        // Written like the releases before typed workbooks: text cells with the close first.
+        let excel_file: String = env::temp_dir()
+            .join(format!("BTCUSD-1h-legacy-{}.xlsx", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let mut workbook: Workbook = Workbook::new();
+        let worksheet: &mut Worksheet = workbook.add_worksheet();
+        worksheet.write_row(0, 0, LEGACY_HEADER).unwrap();
+        page.0.iter().zip(1..).for_each(|(candle_data, row)| {
+            let datetime: String = DateTime::<Utc>::from_timestamp_millis(candle_data[0].as_i64())
+                .unwrap()
+                .to_string();
+            let cells: [String; 8] = [
+                candle_data[2].to_string(),
+                candle_data[1].to_string(),
+                candle_data[3].to_string(),
+                candle_data[4].to_string(),
+                candle_data[5].to_string(),
+                "1h".to_string(),
+                "BTCUSD".to_string(),
+                datetime,
+            ];
+            worksheet.write_row(row, 0, cells).unwrap();
+        });
+        workbook.save(&excel_file).unwrap();
+
+        let imported = read_candles(&excel_file, &ImportSettings::default());
+        fs::remove_file(&excel_file).unwrap();
+
+        let downloaded: Vec<Candle> = page.candles().collect();
+        assert_eq!(downloaded.len(), 24);
+        assert_eq!(
+            imported,
+            vec![("unknown".to_string(), "BTCUSD".to_string(), "1h".to_string(), downloaded)]
+        );
+    }
+}
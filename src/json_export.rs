@@ -0,0 +1,164 @@
+//! NDJSON and JSON array output, written page by page to a file or stdout,
+//! compressed when the file ends in .gz or .zst.
+use std::io::{BufWriter, Write};
+
+use serde::Serialize;
+
+use crate::{
+    compression::{create, CompressedWriter},
+    exchanges::bitfinex::CandleData,
+    output::fail,
+};
+
+/// How the candles are written as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsonSettings {
+    /// Path of the file, `-` for stdout, by default {ticker}-{interval}-{date}.ndjson.
+    pub file: Option<String>,
+    /// A single JSON array instead of one object per line.
+    pub array: bool,
+}
+
+impl JsonSettings {
+    /// Extension of the default file name.
+    pub fn extension(&self) -> &str {
+        if self.array {
+            ".json"
+        } else {
+            ".ndjson"
+        }
+    }
+}
+
+/// A candle with named fields.
+#[derive(Serialize)]
+struct JsonCandle<'a> {
+    ts: i64,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: f64,
+    pair: &'a str,
+    interval: &'a str,
+    exchange: &'a str,
+}
+
+/// File or stdout that receives the candles as they are downloaded.
+pub struct JsonWriter {
+    writer: BufWriter<CompressedWriter<Box<dyn Write>>>,
+    array: bool,
+    written: usize,
+}
+
+impl JsonWriter {
+    /// Opens the file, `-` writes to stdout.
+    /// ```text
+    /// Args:
+    /// json_file: &str: Path of the file or `-`, .gz and .zst files are compressed.
+    /// array: bool: Write a JSON array instead of NDJSON.
+    /// append: bool: Append NDJSON lines to the existing file instead of replacing it.
+    /// ```
+    pub fn new(json_file: &str, array: bool, append: bool) -> Self {
+        let writer: BufWriter<CompressedWriter<Box<dyn Write>>> =
+            BufWriter::new(create(json_file, append));
+        let mut json_writer: Self = Self { writer, array, written: 0 };
+        if array {
+            json_writer.write("[");
+        }
+        json_writer
+    }
+
+    fn write(&mut self, text: &str) {
+        self.writer.write_all(text.as_bytes()).unwrap_or_else(|_| fail("Writing JSON failed"));
+    }
+
+    /// Appends a page of candles, NDJSON lines are flushed as soon as they are written.
+    pub fn write_page(&mut self, page: &CandleData, pair: &str, interval: &str, exchange: &str) {
+        page.candles().for_each(|candle| {
+            let json: String = serde_json::to_string(&JsonCandle {
+                ts: candle.timestamp,
+                open: candle.open,
+                high: candle.high,
+                low: candle.low,
+                close: candle.close,
+                volume: candle.volume,
+                pair,
+                interval,
+                exchange,
+            })
+            .unwrap_or_else(|_| fail("Could not serialize the candle"));
+            let separator: &str = match (self.array, self.written) {
+                (true, 0) => "\n",
+                (true, _) => ",\n",
+                (false, _) => "",
+            };
+            let line: String = separator.to_string() + &json + if self.array { "" } else { "\n" };
+            self.write(&line);
+            self.written += 1;
+        });
+        self.writer.flush().unwrap_or_else(|_| fail("Writing JSON failed"));
+    }
+
+    /// Closes the array, flushes the output and ends the compressed stream.
+    pub fn close(mut self) {
+        if self.array {
+            self.write("\n]\n");
+        }
+        self.writer
+            .into_inner()
+            .map_err(|_| ())
+            .and_then(|writer| writer.finish().map_err(|_| ()))
+            .unwrap_or_else(|_| fail("Writing JSON failed"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+    use crate::exchanges::bitfinex::FloatOrInt;
+
+    fn write(array: bool) -> String {
+        let json_file: String = env::temp_dir()
+            .join(format!("crypto-candlesticks-{}-{array}.json", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let mut writer: JsonWriter = JsonWriter::new(&json_file, array, false);
+        [1_577_808_000_000, 1_577_808_060_000].iter().for_each(|timestamp| {
+            writer.write_page(
+                &CandleData(vec![[
+                    FloatOrInt::Int(*timestamp),
+                    FloatOrInt::Float(7203.0),
+                    FloatOrInt::Float(7201.0),
+                    FloatOrInt::Float(7203.7),
+                    FloatOrInt::Float(7200.1),
+                    FloatOrInt::Float(9.5),
+                ]]),
+                "BTCUSD",
+                "1m",
+                "Bitfinex",
+            )
+        });
+        writer.close();
+        let written: String = fs::read_to_string(&json_file).unwrap();
+        fs::remove_file(&json_file).unwrap();
+        written
+    }
+
+    #[test]
+    fn writes_ndjson_and_arrays() {
+        let line = |ts: i64| {
+            format!(
+                "{{\"ts\":{ts},\"open\":7203.0,\"high\":7203.7,\"low\":7200.1,\"close\":7201.0,\
+                 \"volume\":9.5,\"pair\":\"BTCUSD\",\"interval\":\"1m\",\"exchange\":\"Bitfinex\"}}"
+            )
+        };
+        let (first, second) = (line(1_577_808_000_000), line(1_577_808_060_000));
+        assert_eq!(write(false), format!("{first}\n{second}\n"));
+        assert_eq!(write(true), format!("[\n{first},\n{second}\n]\n"));
+        let parsed: serde_json::Value = serde_json::from_str(&write(true)).unwrap();
+        assert_eq!(parsed.as_array().map(Vec::len), Some(2));
+    }
+}
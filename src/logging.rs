@@ -0,0 +1,54 @@
+//! Leveled, structured logging for diagnostics.
+use std::{fs::OpenOptions, io, sync::Mutex};
+
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+use crate::output::{self, fail};
+
+/// Maps the -v and -q flags to a log level, warnings are shown by default.
+fn level(verbosity: i64) -> LevelFilter {
+    match verbosity {
+        i64::MIN..=-2 => LevelFilter::OFF,
+        -1 => LevelFilter::ERROR,
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Starts logging to stderr and optionally to a file.
+/// ```text
+/// Args:
+/// verbosity: i64: Number of -v minus number of -q.
+/// log_file: Option<&str>: File where the logs are appended as JSON.
+///
+/// RUST_LOG overrides the level (ie. RUST_LOG=crypto_candlesticks_rust=debug,reqwest=trace).
+/// Logs on stderr are JSON when --output json is used.
+/// Only the first call installs the subscriber, ie. when the tests run main several times.
+/// ```
+pub fn init_logging(verbosity: i64, log_file: Option<&str>) {
+    let filter: EnvFilter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(format!("{}={}", env!("CARGO_CRATE_NAME"), level(verbosity)))
+    });
+    let file_layer = log_file.map(|log_file| {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)
+            .unwrap_or_else(|_| fail(&format!("Could not open the log file {log_file}")));
+        fmt::layer().json().with_writer(Mutex::new(file))
+    });
+    let (text_layer, json_layer) = if output::is_json() {
+        (None, Some(fmt::layer().json().with_writer(io::stderr)))
+    } else {
+        (Some(fmt::layer().with_writer(io::stderr)), None)
+    };
+    let _ = tracing_subscriber::registry()
+        .with(filter)
+        .with(text_layer)
+        .with(json_layer)
+        .with(file_layer)
+        .try_init();
+}
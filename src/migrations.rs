@@ -0,0 +1,189 @@
+//! Schema versions of the SQLite database, stored in PRAGMA user_version.
+use rusqlite::{Connection, Transaction};
+use tracing::info;
+
+use crate::output::fail;
+
+/// Every migration in order, a database is at version N after the first N ran.
+/// ```text
+/// Databases created before versioning are at version 0 and go through all of them.
+/// ```
+const MIGRATIONS: [&str; 3] = [
+    // 1: Original schema.
+    "CREATE TABLE IF NOT EXISTS Candlestick(
+    ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    Timestamp REAL,
+    Open REAL,
+    Close REAL,
+    High REAL,
+    Low REAL,
+    Volume REAL,
+    Ticker TEXT,
+    Interval TEXT);",
+    // 2: Integer timestamps, unique candles per ticker, interval and timestamp.
+    // Releases before versioning stored the close in Open and the open in Close.
+    "CREATE TABLE Candlestick_New(
+    ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    Timestamp INTEGER NOT NULL,
+    Open REAL,
+    Close REAL,
+    High REAL,
+    Low REAL,
+    Volume REAL,
+    Ticker TEXT NOT NULL,
+    Interval TEXT NOT NULL,
+    UNIQUE(Ticker, Interval, Timestamp));
+    INSERT INTO Candlestick_New (Timestamp, Open, Close, High, Low, Volume, Ticker, Interval)
+    SELECT CAST(Timestamp AS INTEGER), Close, Open, High, Low, Volume, Ticker, Interval
+    FROM Candlestick
+    WHERE Timestamp IS NOT NULL AND Ticker IS NOT NULL AND Interval IS NOT NULL
+    ORDER BY ID
+    ON CONFLICT(Ticker, Interval, Timestamp) DO UPDATE SET
+    Open = excluded.Open,
+    Close = excluded.Close,
+    High = excluded.High,
+    Low = excluded.Low,
+    Volume = excluded.Volume;
+    DROP TABLE Candlestick;
+    ALTER TABLE Candlestick_New RENAME TO Candlestick;
+    CREATE INDEX IF NOT EXISTS Candlestick_Timestamp ON Candlestick(Timestamp);",
+    // 3: Provenance of every download.
+    "CREATE TABLE Download(
+    ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    RunTimestamp INTEGER NOT NULL,
+    ToolVersion TEXT NOT NULL,
+    Exchange TEXT NOT NULL,
+    Endpoint TEXT NOT NULL,
+    Ticker TEXT NOT NULL,
+    Interval TEXT NOT NULL,
+    RequestedStart INTEGER NOT NULL,
+    RequestedEnd INTEGER NOT NULL,
+    ActualStart INTEGER,
+    ActualEnd INTEGER,
+    Requests INTEGER NOT NULL,
+    Candles INTEGER NOT NULL,
+    Gaps INTEGER NOT NULL);",
+];
+
+/// Version of the schema written by this release.
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Reads PRAGMA user_version.
+pub fn schema_version(connection: &Connection) -> i64 {
+    connection
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .unwrap_or_else(|_| fail("Could not read the database schema version"))
+}
+
+/// Refuses databases written by a newer release.
+pub fn check_version(connection: &Connection) -> i64 {
+    let version: i64 = schema_version(connection);
+    if version > SCHEMA_VERSION {
+        fail(&format!(
+            "The database schema version {version} is newer than the supported version \
+             {SCHEMA_VERSION}, please update crypto-candlesticks-rust"
+        ));
+    }
+    version
+}
+
+/// Upgrades the database in place to SCHEMA_VERSION.
+/// ```text
+/// Each migration runs in its own transaction together with the version bump,
+/// a failed migration leaves the database at the previous version.
+/// ```
+pub fn migrate(connection: &mut Connection) {
+    let version: i64 = check_version(connection);
+    MIGRATIONS.iter().enumerate().skip(version as usize).for_each(|(index, migration)| {
+        let target: i64 = index as i64 + 1;
+        let transaction: Transaction = connection
+            .transaction()
+            .unwrap_or_else(|_| fail("Could not start a database transaction"));
+        transaction
+            .execute_batch(&format!("{migration}\nPRAGMA user_version = {target};"))
+            .unwrap_or_else(|_| {
+                fail(&format!("Could not migrate the database to version {target}"))
+            });
+        transaction.commit().unwrap_or_else(|_| {
+            fail(&format!("Could not migrate the database to version {target}"))
+        });
+        info!(version = target, "database migrated");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_latest_schema() {
+        let mut connection: Connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection);
+        migrate(&mut connection);
+        assert_eq!(schema_version(&connection), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn upgrades_unversioned_database() {
+        let mut connection: Connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE Candlestick(
+                ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                Timestamp REAL, Open REAL, Close REAL, High REAL, Low REAL, Volume REAL,
+                Ticker TEXT, Interval TEXT);
+                INSERT INTO Candlestick (Timestamp, Open, Close, High, Low, Volume, Ticker, Interval)
+                VALUES (1609459200000.0, 1, 1, 1, 1, 1, 'BTCUSD', '1m'),
+                (1609459260000.0, 2, 2, 2, 2, 2, 'BTCUSD', '1m'),
+                (1609459200000.0, 3, 3, 3, 3, 3, 'BTCUSD', '1m');",
+            )
+            .unwrap();
+        migrate(&mut connection);
+
+        let (rows, open, timestamp_type): (i64, f64, String) = connection
+            .query_row(
+                "SELECT COUNT(*), MAX(CASE WHEN Timestamp = 1609459200000 THEN Open END),
+                MIN(typeof(Timestamp)) FROM Candlestick",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((rows, open, timestamp_type.as_str()), (2, 3.0, "integer"));
+        assert_eq!(schema_version(&connection), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn swaps_open_and_close_of_legacy_rows() {
+        let mut connection: Connection = Connection::open_in_memory().unwrap();
+        // [1604275200000,13755,13562,13836,13207,6586.08653832] as the first release stored it.
+        connection
+            .execute_batch(
+                "CREATE TABLE Candlestick(
+                ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                Timestamp REAL, Open REAL, Close REAL, High REAL, Low REAL, Volume REAL,
+                Ticker TEXT, Interval TEXT);
+                INSERT INTO Candlestick (Timestamp, Open, Close, High, Low, Volume, Ticker, Interval)
+                VALUES (1604275200000.0, 13562, 13755, 13836, 13207, 6586.08653832, 'BTCUSD', '1D');",
+            )
+            .unwrap();
+        migrate(&mut connection);
+
+        let row: (i64, f64, f64, f64, f64, f64) = connection
+            .query_row(
+                "SELECT Timestamp, Open, High, Low, Close, Volume FROM Candlestick",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(row, (1_604_275_200_000, 13755.0, 13836.0, 13207.0, 13562.0, 6586.08653832));
+    }
+}
@@ -0,0 +1,139 @@
+//! Console output, either colored text or newline-delimited JSON.
+use std::{
+    fs, mem, panic, process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+use colorful::Colorful;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Format of everything written to stdout and stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Colored text for humans.
+    Text,
+    /// One JSON object per line, events on stdout and errors on stderr.
+    Json,
+}
+
+static OUTPUT_MODE: OnceLock<OutputMode> = OnceLock::new();
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+/// Files written while a run is in progress, removed when it fails.
+static TEMPORARY_FILES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Selects the output format, panics are reported as JSON errors in JSON mode.
+pub fn set_output_mode(mode: OutputMode) {
+    if OUTPUT_MODE.set(mode).is_ok() && mode == OutputMode::Json {
+        panic::set_hook(Box::new(|panic_info| {
+            let message: String = match panic_info.payload().downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => panic_info.payload().downcast_ref::<&str>().unwrap_or(&"panic").to_string(),
+            };
+            eprintln!("{}", json!({ "event": "error", "message": message }));
+        }));
+    }
+}
+
+/// Whether the output is newline-delimited JSON.
+pub fn is_json() -> bool {
+    OUTPUT_MODE.get() == Some(&OutputMode::Json)
+}
+
+/// Candles are streamed to stdout, every message and event goes to stderr instead.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+/// Whether stdout only carries candles.
+pub fn is_stdout_reserved() -> bool {
+    STDOUT_RESERVED.load(Ordering::Relaxed)
+}
+
+/// Prints a line to stdout, or to stderr when stdout is reserved.
+pub fn print_line(text: &str) {
+    if is_stdout_reserved() {
+        eprintln!("{text}");
+    } else {
+        println!("{text}");
+    }
+}
+
+/// Prints a message with the given level.
+fn message(level: &str, text: &str) {
+    if is_json() {
+        print_line(&json!({ "event": "message", "level": level, "message": text }).to_string());
+    } else {
+        match level {
+            "success" => print_line(&text.green().to_string()),
+            "info" | "warning" => print_line(&text.yellow().to_string()),
+            _ => print_line(text),
+        }
+    }
+}
+
+/// Prints a step of the run.
+pub fn info(text: &str) {
+    message("info", text);
+}
+
+/// Prints a step that completed.
+pub fn success(text: &str) {
+    message("success", text);
+}
+
+/// Prints something the user should look at.
+pub fn warning(text: &str) {
+    message("warning", text);
+}
+
+/// Prints an error to stderr.
+pub fn error(text: &str) {
+    if is_json() {
+        eprintln!("{}", json!({ "event": "error", "message": text }));
+    } else {
+        eprintln!("{}", text.red());
+    }
+}
+
+/// Removes the file if the run fails before it is kept.
+pub fn remove_on_fail(file: &str) {
+    TEMPORARY_FILES.lock().unwrap_or_else(|error| error.into_inner()).push(file.to_string());
+}
+
+/// The file was renamed or removed, a failure leaves it alone.
+pub fn keep_on_fail(file: &str) {
+    TEMPORARY_FILES.lock().unwrap_or_else(|error| error.into_inner()).retain(|other| other != file);
+}
+
+/// Prints an error to stderr and exits, the temporary files of the run are removed first.
+pub fn fail(text: &str) -> ! {
+    error(text);
+    let files: Vec<String> =
+        mem::take(&mut *TEMPORARY_FILES.lock().unwrap_or_else(|error| error.into_inner()));
+    files.iter().for_each(|file| {
+        let _ = fs::remove_file(file);
+    });
+    process::exit(1);
+}
+
+/// Emits a JSON event, nothing is printed in text mode.
+/// ```text
+/// Args:
+/// name: &str: Value of the "event" field.
+/// payload: &T: Object whose fields are added to the event.
+/// ```
+pub fn event<T: Serialize>(name: &str, payload: &T) {
+    if is_json() {
+        let mut event: Value = json!({ "event": name });
+        if let (Some(event), Ok(Value::Object(fields))) =
+            (event.as_object_mut(), serde_json::to_value(payload))
+        {
+            event.extend(fields);
+        }
+        print_line(&event.to_string());
+    }
+}
@@ -0,0 +1,193 @@
+//! Apache Parquet output with a typed schema, written page by page.
+use std::{fs::File, sync::Arc};
+
+use arrow_array::{
+    types::Int32Type, ArrayRef, DictionaryArray, Float64Array, Int32Array, RecordBatch,
+    StringArray, TimestampMillisecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, GzipLevel, ZstdLevel},
+    file::properties::WriterProperties,
+};
+
+use crate::{exchanges::bitfinex::CandleData, output::fail};
+
+/// Compression codecs accepted by --parquet_compression.
+pub const COMPRESSIONS: [&str; 4] = ["snappy", "zstd", "gzip", "none"];
+
+/// How the Parquet file is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParquetSettings {
+    /// One of COMPRESSIONS.
+    pub compression: String,
+    /// Rows buffered before a row group is flushed to the file.
+    pub row_group_size: usize,
+}
+
+impl Default for ParquetSettings {
+    fn default() -> Self {
+        Self { compression: "snappy".to_string(), row_group_size: 1024 * 1024 }
+    }
+}
+
+/// Timestamp in ms since the epoch in UTC, dictionary encoded ticker and interval.
+fn schema() -> SchemaRef {
+    let dictionary = || DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+    Arc::new(Schema::new(vec![
+        Field::new("ts", DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())), false),
+        Field::new("ticker", dictionary(), false),
+        Field::new("interval", dictionary(), false),
+        Field::new("open", DataType::Float64, false),
+        Field::new("high", DataType::Float64, false),
+        Field::new("low", DataType::Float64, false),
+        Field::new("close", DataType::Float64, false),
+        Field::new("volume", DataType::Float64, false),
+    ]))
+}
+
+fn compression(name: &str) -> Compression {
+    match name {
+        "snappy" => Compression::SNAPPY,
+        "zstd" => Compression::ZSTD(ZstdLevel::default()),
+        "gzip" => Compression::GZIP(GzipLevel::default()),
+        "none" => Compression::UNCOMPRESSED,
+        _ => fail(&format!(
+            "Unsupported Parquet compression: {name}, supported codecs are {}",
+            COMPRESSIONS.join(", ")
+        )),
+    }
+}
+
+/// Parquet file that receives the candles as they are downloaded.
+pub struct ParquetWriter {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+}
+
+impl ParquetWriter {
+    /// Creates the file.
+    /// ```text
+    /// Args:
+    /// parquet_file: &str: Path of the file.
+    /// settings: &ParquetSettings: Compression and row group size.
+    /// ```
+    pub fn new(parquet_file: &str, settings: &ParquetSettings) -> Self {
+        let file: File = File::create(parquet_file)
+            .unwrap_or_else(|_| fail(&format!("Could not create {parquet_file}")));
+        let properties: WriterProperties = WriterProperties::builder()
+            .set_compression(compression(&settings.compression))
+            .set_max_row_group_size(settings.row_group_size.max(1))
+            .build();
+        let schema: SchemaRef = schema();
+        let writer: ArrowWriter<File> =
+            ArrowWriter::try_new(file, schema.clone(), Some(properties))
+                .unwrap_or_else(|_| fail("Could not write the Parquet schema"));
+        Self { writer, schema }
+    }
+
+    /// Appends a page of candles, full row groups are flushed to the file.
+    pub fn write_page(&mut self, page: &CandleData, ticker: &str, interval: &str) {
+        if page.0.is_empty() {
+            return;
+        }
+        let rows: usize = page.0.len();
+        let column = |index: usize| -> ArrayRef {
+            Arc::new(page.0.iter().map(|candle| candle[index].as_f64()).collect::<Float64Array>())
+        };
+        let dictionary = |value: &str| -> ArrayRef {
+            Arc::new(DictionaryArray::<Int32Type>::new(
+                Int32Array::from(vec![0; rows]),
+                Arc::new(StringArray::from(vec![value])),
+            ))
+        };
+        let timestamps: TimestampMillisecondArray = TimestampMillisecondArray::from(
+            page.0.iter().map(|candle| candle[0].as_i64()).collect::<Vec<i64>>(),
+        )
+        .with_timezone("UTC");
+        let batch: RecordBatch = RecordBatch::try_new(
+            self.schema.clone(),
+            vec![
+                Arc::new(timestamps),
+                dictionary(ticker),
+                dictionary(interval),
+                column(1),
+                column(3),
+                column(4),
+                column(2),
+                column(5),
+            ],
+        )
+        .unwrap_or_else(|_| fail("Could not convert the candles to Parquet"));
+        self.writer.write(&batch).unwrap_or_else(|_| fail("Writing to Parquet failed"));
+    }
+
+    /// Flushes the last row group and writes the footer.
+    pub fn close(self) {
+        self.writer.close().unwrap_or_else(|_| fail("Writing to Parquet failed"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use arrow_array::Array;
+    use parquet::{
+        arrow::arrow_reader::ParquetRecordBatchReaderBuilder, file::reader::FileReader,
+        file::serialized_reader::SerializedFileReader,
+    };
+
+    use super::*;
+    use crate::exchanges::bitfinex::FloatOrInt;
+
+    fn page(timestamps: &[i64]) -> CandleData {
+        CandleData(
+            timestamps
+                .iter()
+                .map(|timestamp| {
+                    [
+                        FloatOrInt::Int(*timestamp),
+                        FloatOrInt::Float(10.0),
+                        FloatOrInt::Float(11.0),
+                        FloatOrInt::Float(12.0),
+                        FloatOrInt::Float(9.0),
+                        FloatOrInt::Float(1.5),
+                    ]
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn writes_typed_row_groups() {
+        let parquet_file: String = env::temp_dir()
+            .join(format!("crypto-candlesticks-{}.parquet", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let settings: ParquetSettings =
+            ParquetSettings { compression: "zstd".to_string(), row_group_size: 2 };
+        let mut writer: ParquetWriter = ParquetWriter::new(&parquet_file, &settings);
+        writer.write_page(&page(&[1_609_459_200_000, 1_609_459_260_000]), "BTCUSD", "1m");
+        writer.write_page(&page(&[1_609_459_320_000]), "BTCUSD", "1m");
+        writer.close();
+
+        let row_groups: usize =
+            SerializedFileReader::new(File::open(&parquet_file).unwrap()).unwrap().num_row_groups();
+        let batches: Vec<RecordBatch> =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&parquet_file).unwrap())
+                .unwrap()
+                .build()
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        fs::remove_file(&parquet_file).unwrap();
+
+        assert_eq!(row_groups, 2);
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 3);
+        assert_eq!(batches[0].schema().fields(), schema().fields());
+        let open = batches[0].column(3).as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(open.value(0), 10.0);
+    }
+}
@@ -0,0 +1,237 @@
+//! Reads candles back out of an existing database.
+use std::{
+    env,
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+use serde_json::json;
+
+use crate::{
+    compression::{codec, open, strip_extension},
+    csv_export::{CsvSettings, CsvWriter},
+    database::{
+        insert_candlesticks, query_candlesticks, read_downloads, CandleQuery, DatabaseSettings,
+        DownloadRecord, UNKNOWN_EXCHANGE,
+    },
+    excel_export::{write_to_excel_file, ExcelSettings},
+    exchanges::bitfinex::{Candle, CandleData},
+    json_export::JsonWriter,
+    output::{self, fail, success, warning},
+    parquet_export::{ParquetSettings, ParquetWriter},
+    text_console::candles_table,
+};
+
+/// Extensions of the files the candles can be exported to.
+/// `-` streams NDJSON to stdout, csv and json files can end in .gz or .zst.
+pub const EXPORT_FORMATS: [&str; 7] =
+    ["sqlite", "xlsx", "parquet", "csv", "ndjson", "jsonl", "json"];
+
+/// Exchange of a series, taken from its latest download.
+fn exchange_of(downloads: &[DownloadRecord], ticker: &str, interval: &str) -> String {
+    downloads
+        .iter()
+        .rev()
+        .find(|download| download.ticker == ticker && download.interval == interval)
+        .map_or_else(|| UNKNOWN_EXCHANGE.to_string(), |download| download.exchange.clone())
+}
+
+/// Writes the selected candles to a file, the format is taken from the extension.
+fn export(
+    series: &[(String, String, Vec<Candle>)],
+    downloads: &[DownloadRecord],
+    export_file: &str,
+    excel_settings: &ExcelSettings,
+) {
+    let extension: String = Path::new(strip_extension(export_file))
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let compressed: bool = codec(export_file) != "none";
+    match (export_file, extension.as_str()) {
+        ("-", _) | (_, "ndjson" | "jsonl" | "json") => {
+            let mut writer: JsonWriter = JsonWriter::new(export_file, extension == "json", false);
+            series.iter().for_each(|(ticker, interval, candles)| {
+                writer.write_page(
+                    &CandleData::from(candles.as_slice()),
+                    ticker,
+                    interval,
+                    &exchange_of(downloads, ticker, interval),
+                );
+            });
+            writer.close();
+        }
+        (_, "sqlite") if !compressed => {
+            let database_settings: DatabaseSettings = DatabaseSettings {
+                data_base_file: Some(export_file.to_string()),
+                ..DatabaseSettings::default()
+            };
+            series.iter().for_each(|(ticker, interval, candles)| {
+                insert_candlesticks(
+                    &[CandleData::from(candles.as_slice())],
+                    ticker,
+                    interval,
+                    &database_settings,
+                );
+            });
+        }
+        (_, "xlsx") if !compressed => write_to_excel_file(
+            export_file,
+            &series
+                .iter()
+                .map(|(ticker, interval, candles)| {
+                    (ticker.clone(), interval.clone(), vec![CandleData::from(candles.as_slice())])
+                })
+                .collect::<Vec<_>>(),
+            excel_settings,
+        ),
+        (_, "parquet") if !compressed => {
+            let mut writer: ParquetWriter =
+                ParquetWriter::new(export_file, &ParquetSettings::default());
+            series.iter().for_each(|(ticker, interval, candles)| {
+                writer.write_page(&CandleData::from(candles.as_slice()), ticker, interval);
+            });
+            writer.close();
+        }
+        (_, "csv") => {
+            let mut writer: CsvWriter = CsvWriter::new(export_file, &CsvSettings::default(), false);
+            series.iter().for_each(|(ticker, interval, candles)| {
+                writer.write_page(&CandleData::from(candles.as_slice()), ticker, interval);
+            });
+            writer.close();
+        }
+        _ => fail(&format!(
+            "Unsupported export format: {export_file}, supported extensions are {} or - for \
+             stdout, only csv and json files can be compressed",
+            EXPORT_FORMATS.join(", ")
+        )),
+    }
+}
+
+/// Decompresses a .sqlite.gz or .sqlite.zst database to a temporary file SQLite can open.
+fn decompress(data_base_file: &str) -> String {
+    let name: String = Path::new(strip_extension(data_base_file))
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temporary: String = env::temp_dir()
+        .join(format!("crypto-candlesticks-{}-{name}", std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let mut file: File =
+        File::create(&temporary).unwrap_or_else(|_| fail(&format!("Could not create {temporary}")));
+    io::copy(&mut open(data_base_file), &mut file)
+        .unwrap_or_else(|_| fail(&format!("Could not decompress {data_base_file}")));
+    temporary
+}
+
+/// Runs the `query` command.
+/// ```text
+/// Args:
+/// data_base_file: &str: Path to the .sqlite file, .sqlite.gz and .sqlite.zst are decompressed.
+/// query: &CandleQuery: Ticker, interval and time range to select.
+/// export_file: Option<&str>: Write the candles to this file instead of printing them.
+/// excel_settings: &ExcelSettings: Layout of .xlsx exports.
+/// ```
+pub fn run_query(
+    data_base_file: &str,
+    query: &CandleQuery,
+    export_file: Option<&str>,
+    excel_settings: &ExcelSettings,
+) {
+    let temporary: Option<String> =
+        (codec(data_base_file) != "none").then(|| decompress(data_base_file));
+    let series: Vec<(String, String, Vec<Candle>)> =
+        query_candlesticks(temporary.as_deref().unwrap_or(data_base_file), query);
+    let downloads: Vec<DownloadRecord> =
+        read_downloads(temporary.as_deref().unwrap_or(data_base_file));
+    if let Some(temporary) = &temporary {
+        let _ = fs::remove_file(temporary);
+    }
+    let candles: usize = series.iter().map(|(_, _, candles)| candles.len()).sum();
+    match export_file {
+        Some(export_file) => {
+            export(&series, &downloads, export_file, excel_settings);
+            success(&format!("Exported {candles} candles to {export_file}"));
+            output::event("exported", &json!({ "file": export_file, "candles": candles }));
+        }
+        None if output::is_json() => series.iter().for_each(|(ticker, interval, candles)| {
+            candles.iter().for_each(|candle| {
+                output::event(
+                    "candle",
+                    &json!({
+                        "ticker": ticker,
+                        "interval": interval,
+                        "timestamp": candle.timestamp,
+                        "open": candle.open,
+                        "high": candle.high,
+                        "low": candle.low,
+                        "close": candle.close,
+                        "volume": candle.volume,
+                    }),
+                )
+            })
+        }),
+        None if candles == 0 => warning("No candles match the query"),
+        None => println!("{}", candles_table(&series)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::record_download;
+
+    #[test]
+    fn exports_the_exchange_of_each_series() {
+        let directory: String = env::temp_dir()
+            .join(format!("crypto-candlesticks-query-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        fs::create_dir_all(&directory).unwrap();
+        let database_settings: DatabaseSettings = DatabaseSettings {
+            data_base_file: Some(format!("{directory}/candles.sqlite")),
+            ..DatabaseSettings::default()
+        };
+        let candle: Candle = Candle {
+            timestamp: 1_609_459_200_000,
+            open: 10.0,
+            close: 11.0,
+            high: 12.0,
+            low: 9.0,
+            volume: 1.5,
+        };
+        ["BTCUSD", "ETHUSD"].iter().for_each(|ticker| {
+            insert_candlesticks(
+                &[CandleData::from([candle].as_slice())],
+                ticker,
+                "1m",
+                &database_settings,
+            );
+        });
+        record_download(
+            &DownloadRecord::new("Synthetic", "synthetic://".to_string(), "BTCUSD", "1m"),
+            &database_settings,
+        );
+
+        let export_file: String = format!("{directory}/candles.ndjson");
+        run_query(
+            &format!("{directory}/candles.sqlite"),
+            &CandleQuery::default(),
+            Some(&export_file),
+            &ExcelSettings::default(),
+        );
+        let exported: String = fs::read_to_string(&export_file).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let exchanges: Vec<serde_json::Value> = exported
+            .lines()
+            .map(|line| {
+                let candle: serde_json::Value = serde_json::from_str(line).unwrap();
+                json!([candle["pair"], candle["exchange"]])
+            })
+            .collect();
+        assert_eq!(exchanges, vec![json!(["BTCUSD", "Synthetic"]), json!(["ETHUSD", "unknown"])]);
+    }
+}
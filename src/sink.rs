@@ -0,0 +1,558 @@
+//! Outputs of a download, selected with --format.
+use std::{fs, path::Path};
+
+use crate::{
+    compression::{codec, extension},
+    csv_export::CsvWriter,
+    database::{insert_candlesticks, record_download, DatabaseSettings, DownloadRecord},
+    excel_export::{write_to_excel_file, ExcelSettings},
+    exchanges::bitfinex::CandleData,
+    file_names::{create_parent_dir, FileNameSettings, TemplateValues},
+    get_data::DownloadSettings,
+    json_export::JsonWriter,
+    output::{self, fail, info, success, warning},
+    parquet_export::ParquetWriter,
+    postgresql::insert_candlesticks_postgres,
+    validation::ValidationReport,
+};
+
+/// Formats accepted by --format, PostgreSQL is written when --postgres is set.
+pub const FORMATS: [&str; 5] = ["sqlite", "xlsx", "parquet", "csv", "json"];
+
+/// The ticker and interval being downloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Series {
+    /// Quote + base asset, ie. BTCUSD.
+    pub ticker: String,
+    /// Quote / base asset, ie. BTC/USD.
+    pub pair: String,
+    pub interval: String,
+    pub exchange: String,
+    /// Endpoint the candles are downloaded from, kept in the download history.
+    pub endpoint: String,
+}
+
+/// Everything downloaded, handed to the sinks once it is validated.
+pub struct Download<'a> {
+    pub data: &'a [CandleData],
+    pub report: &'a ValidationReport,
+    pub requested_start: i64,
+    pub requested_end: i64,
+}
+
+/// Output of a download.
+pub trait Sink {
+    /// Writes a page as soon as it is downloaded, sinks written at the end ignore it.
+    fn write_page(&mut self, _page: &CandleData) {}
+
+    /// Writes what is left.
+    /// ```text
+    /// Returns:
+    /// Option<String>: The file written, None for databases outside of the filesystem.
+    /// ```
+    fn finish(self: Box<Self>, download: &Download) -> Option<String>;
+
+    /// Removes what was written when the data fails --strict validation.
+    fn discard(self: Box<Self>) {}
+}
+
+/// File streamed under a temporary name next to its destination and moved into place once
+/// finished, a discarded or failed download leaves the existing file as it was. `-` is written
+/// directly.
+struct PartialFile {
+    file: String,
+    partial: String,
+}
+
+impl PartialFile {
+    /// Creates the directories of the file, appending starts from a copy of it.
+    fn new(file: &str, append: bool) -> Self {
+        if file == "-" {
+            return Self { file: file.to_string(), partial: file.to_string() };
+        }
+        create_parent_dir(file);
+        let path: &Path = Path::new(file);
+        let name: String =
+            path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        // The name keeps its extension, the writers pick the compression from it.
+        let partial: String = path
+            .with_file_name(format!(".partial-{}-{name}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        output::remove_on_fail(&partial);
+        if append {
+            fs::copy(file, &partial).unwrap_or_else(|_| fail(&format!("Could not copy {file}")));
+        }
+        Self { file: file.to_string(), partial }
+    }
+
+    /// Replaces the file with what was written.
+    fn persist(self) -> String {
+        if self.partial != self.file {
+            fs::rename(&self.partial, &self.file)
+                .unwrap_or_else(|_| fail(&format!("Could not write {}", self.file)));
+            output::keep_on_fail(&self.partial);
+        }
+        self.file
+    }
+
+    fn remove(self) {
+        if self.partial != self.file {
+            let _ = fs::remove_file(&self.partial);
+            output::keep_on_fail(&self.partial);
+        }
+    }
+}
+
+struct SqliteSink {
+    series: Series,
+    settings: DatabaseSettings,
+}
+
+impl Sink for SqliteSink {
+    fn finish(self: Box<Self>, download: &Download) -> Option<String> {
+        let Series { ticker, interval, exchange, endpoint, .. } = &self.series;
+        if let Some(data_base_file) = &self.settings.data_base_file {
+            create_parent_dir(data_base_file);
+        }
+        let data_base_file: String =
+            insert_candlesticks(download.data, ticker, interval, &self.settings);
+        record_download(
+            &DownloadRecord {
+                requested_start: download.requested_start,
+                requested_end: download.requested_end,
+                actual_start: download.report.first_timestamp,
+                actual_end: download.report.last_timestamp,
+                requests: download.data.len(),
+                candles: download.report.candles,
+                gaps: download.report.gaps,
+                ..DownloadRecord::new(exchange, endpoint.clone(), ticker, interval)
+            },
+            &self.settings,
+        );
+        success("Writing to database completed! 🚀🚀");
+        Some(data_base_file)
+    }
+}
+
+struct PostgresSink {
+    series: Series,
+    settings: DatabaseSettings,
+}
+
+impl Sink for PostgresSink {
+    fn finish(self: Box<Self>, download: &Download) -> Option<String> {
+        if let Some(postgres_settings) = &self.settings.postgres {
+            let Series { ticker, interval, exchange, .. } = &self.series;
+            insert_candlesticks_postgres(
+                download.data,
+                exchange,
+                ticker,
+                interval,
+                postgres_settings,
+            );
+            success("Writing to PostgreSQL completed! 🚀🚀");
+        }
+        None
+    }
+}
+
+struct ExcelSink {
+    series: Series,
+    file: String,
+    settings: ExcelSettings,
+}
+
+impl Sink for ExcelSink {
+    fn finish(self: Box<Self>, download: &Download) -> Option<String> {
+        info("Writing to Excel...");
+        create_parent_dir(&self.file);
+        write_to_excel_file(
+            &self.file,
+            &[(self.series.ticker.clone(), self.series.interval.clone(), download.data.to_vec())],
+            &self.settings,
+        );
+        success("Writing to Excel completed! 🚀🚀🚀");
+        Some(self.file)
+    }
+}
+
+struct ParquetSink {
+    series: Series,
+    file: PartialFile,
+    writer: ParquetWriter,
+}
+
+impl Sink for ParquetSink {
+    fn write_page(&mut self, page: &CandleData) {
+        self.writer.write_page(page, &self.series.ticker, &self.series.interval);
+    }
+
+    fn finish(self: Box<Self>, _download: &Download) -> Option<String> {
+        self.writer.close();
+        success("Writing to Parquet completed! 🚀");
+        Some(self.file.persist())
+    }
+
+    fn discard(self: Box<Self>) {
+        drop(self.writer);
+        self.file.remove();
+    }
+}
+
+struct CsvSink {
+    series: Series,
+    file: PartialFile,
+    writer: CsvWriter,
+}
+
+impl Sink for CsvSink {
+    fn write_page(&mut self, page: &CandleData) {
+        self.writer.write_page(page, &self.series.pair, &self.series.interval);
+    }
+
+    fn finish(self: Box<Self>, _download: &Download) -> Option<String> {
+        self.writer.close();
+        success("Writing to CSV completed! 🚀");
+        Some(self.file.persist())
+    }
+
+    fn discard(self: Box<Self>) {
+        drop(self.writer);
+        self.file.remove();
+    }
+}
+
+struct JsonSink {
+    series: Series,
+    file: PartialFile,
+    writer: JsonWriter,
+}
+
+impl Sink for JsonSink {
+    fn write_page(&mut self, page: &CandleData) {
+        let Series { ticker, interval, exchange, .. } = &self.series;
+        self.writer.write_page(page, ticker, interval, exchange);
+    }
+
+    fn finish(self: Box<Self>, _download: &Download) -> Option<String> {
+        self.writer.close();
+        success("Writing to JSON completed! 🚀");
+        Some(self.file.persist())
+    }
+
+    fn discard(self: Box<Self>) {
+        drop(self.writer);
+        self.file.remove();
+    }
+}
+
+/// Creates the sinks of the selected formats.
+/// ```text
+/// Every file is checked against --if_exists before any of them is created, existing
+/// files are skipped or refused when they cannot be appended to. The --database file is
+/// shared by every pair and upserted, it is never skipped. Directories are only created
+/// for the files that are opened.
+///
+/// Args:
+/// series: &Series: Ticker and interval being downloaded.
+/// values: &TemplateValues: Values of the file name template.
+/// settings: &DownloadSettings: Formats and their settings.
+///
+/// Returns:
+/// Vec<Box<dyn Sink>>: The sinks in the order of --format, PostgreSQL last.
+/// ```
+pub fn open_sinks(
+    series: &Series,
+    values: &TemplateValues,
+    settings: &DownloadSettings,
+) -> Vec<Box<dyn Sink>> {
+    let files: &FileNameSettings = &settings.files;
+    let compressed: &str = extension(&settings.text_compression);
+    let planned: Vec<(&str, String)> = settings
+        .formats
+        .iter()
+        .map(|format| {
+            let file: String = match format.as_str() {
+                "sqlite" => settings
+                    .database
+                    .data_base_file
+                    .clone()
+                    .unwrap_or_else(|| files.data_base_path(values)),
+                "json" => match settings.json.file.clone() {
+                    // Names that already end in .gz or .zst keep the codec they name.
+                    Some(file) if file == "-" || codec(&file) != "none" => file,
+                    Some(file) => file + compressed,
+                    None => {
+                        files.path(values, &(settings.json.extension().to_string() + compressed))
+                    }
+                },
+                "csv" => files.path(values, &(".csv".to_string() + compressed)),
+                "xlsx" | "parquet" => files.path(values, &format!(".{format}")),
+                _ => fail(&format!(
+                    "Unsupported format: {format}, supported formats are {}",
+                    FORMATS.join(", ")
+                )),
+            };
+            (format.as_str(), file)
+        })
+        .filter(|(format, file)| {
+            let shared: bool = *format == "sqlite" && settings.database.data_base_file.is_some();
+            let skipped: bool = !shared && files.skips(file);
+            if skipped {
+                warning(&format!("{file} already exists, skipping it"));
+            }
+            !skipped
+        })
+        .collect();
+    planned
+        .iter()
+        .filter(|(format, file)| {
+            let appendable: bool = match *format {
+                "json" => !settings.json.array,
+                "xlsx" | "parquet" => false,
+                _ => true,
+            };
+            !appendable && files.appends(file)
+        })
+        .for_each(|(_, file)| {
+            fail(&format!(
+                "{file} already exists and cannot be appended to, use --if_exists overwrite or skip"
+            ))
+        });
+
+    let mut sinks: Vec<Box<dyn Sink>> = planned
+        .into_iter()
+        .map(|(format, file)| -> Box<dyn Sink> {
+            let append: bool = files.appends(&file);
+            match format {
+                "sqlite" => Box::new(SqliteSink {
+                    series: series.clone(),
+                    settings: DatabaseSettings {
+                        data_base_file: Some(file),
+                        ..settings.database.clone()
+                    },
+                }),
+                "xlsx" => Box::new(ExcelSink {
+                    series: series.clone(),
+                    file,
+                    settings: settings.excel.clone(),
+                }),
+                "parquet" => {
+                    let file: PartialFile = PartialFile::new(&file, false);
+                    Box::new(ParquetSink {
+                        series: series.clone(),
+                        writer: ParquetWriter::new(&file.partial, &settings.parquet),
+                        file,
+                    })
+                }
+                "csv" => {
+                    let file: PartialFile = PartialFile::new(&file, append);
+                    Box::new(CsvSink {
+                        series: series.clone(),
+                        writer: CsvWriter::new(&file.partial, &settings.csv, append),
+                        file,
+                    })
+                }
+                _ => {
+                    let file: PartialFile = PartialFile::new(&file, append);
+                    Box::new(JsonSink {
+                        series: series.clone(),
+                        writer: JsonWriter::new(&file.partial, settings.json.array, append),
+                        file,
+                    })
+                }
+            }
+        })
+        .collect();
+    if settings.database.postgres.is_some() {
+        sinks.push(Box::new(PostgresSink {
+            series: series.clone(),
+            settings: settings.database.clone(),
+        }));
+    }
+    sinks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, io::Read, path::Path};
+
+    use super::*;
+    use crate::{
+        compression::open,
+        exchanges::bitfinex::{Candle, FloatOrInt},
+        json_export::JsonSettings,
+        validation::validate_candles,
+    };
+
+    #[test]
+    fn writes_the_selected_formats() {
+        let output_dir: String = env::temp_dir()
+            .join(format!("crypto-candlesticks-sinks-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let settings: DownloadSettings = DownloadSettings {
+            formats: vec!["csv".to_string(), "sqlite".to_string(), "json".to_string()],
+            files: FileNameSettings {
+                output_dir: Some(output_dir.clone()),
+                template: Some("{symbol}{base}-{interval}".to_string()),
+                if_exists: "skip".to_string(),
+            },
+            json: JsonSettings {
+                file: Some(
+                    Path::new(&output_dir).join("candles.ndjson").to_string_lossy().to_string(),
+                ),
+                array: false,
+            },
+            text_compression: "gzip".to_string(),
+            ..DownloadSettings::default()
+        };
+        let series: Series = Series {
+            ticker: "BTCUSD".to_string(),
+            pair: "BTC/USD".to_string(),
+            interval: "1m".to_string(),
+            exchange: "Bitfinex".to_string(),
+            endpoint: "https://api-pub.bitfinex.com/v2/candles/trade:1m:tBTCUSD/hist".to_string(),
+        };
+        let values: TemplateValues = TemplateValues::new(
+            "Bitfinex",
+            "BTC",
+            "USD",
+            "1m",
+            1_609_459_200_000,
+            1_609_459_260_000,
+        );
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::write(Path::new(&output_dir).join("BTCUSD-1m.csv.gz"), "existing").unwrap();
+
+        let mut sinks: Vec<Box<dyn Sink>> = open_sinks(&series, &values, &settings);
+        let page: CandleData = CandleData(vec![[
+            FloatOrInt::Int(1_609_459_200_000),
+            FloatOrInt::Float(10.0),
+            FloatOrInt::Float(11.0),
+            FloatOrInt::Float(12.0),
+            FloatOrInt::Float(9.0),
+            FloatOrInt::Float(1.5),
+        ]]);
+        sinks.iter_mut().for_each(|sink| sink.write_page(&page));
+        let candles: Vec<Candle> = page.candles().collect();
+        let report: ValidationReport = validate_candles("BTCUSD", "1m", &candles);
+        let data: Vec<CandleData> = vec![page];
+        let download: Download = Download {
+            data: &data,
+            report: &report,
+            requested_start: 1_609_459_200_000,
+            requested_end: 1_609_459_260_000,
+        };
+        let files: Vec<String> =
+            sinks.into_iter().filter_map(|sink| sink.finish(&download)).collect();
+        let in_output_dir =
+            |file: &str| Path::new(&output_dir).join(file).to_string_lossy().to_string();
+        let csv: String = fs::read_to_string(in_output_dir("BTCUSD-1m.csv.gz")).unwrap();
+        let mut ndjson: String = String::new();
+        open(&in_output_dir("candles.ndjson.gz")).read_to_string(&mut ndjson).unwrap();
+        // The database of the pair is skipped now that it exists, unless it is shared.
+        let sqlite: DownloadSettings = DownloadSettings {
+            formats: vec!["sqlite".to_string()],
+            files: settings.files.clone(),
+            ..DownloadSettings::default()
+        };
+        let shared: DownloadSettings = DownloadSettings {
+            database: DatabaseSettings {
+                data_base_file: Some(in_output_dir("BTCUSD-1m.sqlite")),
+                ..DatabaseSettings::default()
+            },
+            ..sqlite.clone()
+        };
+        let reopened: (usize, usize) = (
+            open_sinks(&series, &values, &sqlite).len(),
+            open_sinks(&series, &values, &shared).len(),
+        );
+        fs::remove_dir_all(&output_dir).unwrap();
+
+        // --json_file gets the suffix of --text_compression too.
+        assert_eq!(reopened, (0, 1));
+        assert_eq!(
+            files,
+            vec![in_output_dir("BTCUSD-1m.sqlite"), in_output_dir("candles.ndjson.gz")]
+        );
+        assert_eq!(csv, "existing");
+        assert_eq!(ndjson.lines().count(), 1);
+    }
+
+    #[test]
+    fn discarding_leaves_existing_files_untouched() {
+        let output_dir: String = env::temp_dir()
+            .join(format!("crypto-candlesticks-discard-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let series: Series = Series {
+            ticker: "BTCUSD".to_string(),
+            pair: "BTC/USD".to_string(),
+            interval: "1m".to_string(),
+            exchange: "Bitfinex".to_string(),
+            endpoint: "https://api-pub.bitfinex.com/v2/candles/trade:1m:tBTCUSD/hist".to_string(),
+        };
+        let values: TemplateValues = TemplateValues::new(
+            "Bitfinex",
+            "BTC",
+            "USD",
+            "1m",
+            1_609_459_200_000,
+            1_609_459_260_000,
+        );
+        let page: CandleData = CandleData(vec![[
+            FloatOrInt::Int(1_609_459_200_000),
+            FloatOrInt::Float(10.0),
+            FloatOrInt::Float(11.0),
+            FloatOrInt::Float(12.0),
+            FloatOrInt::Float(9.0),
+            FloatOrInt::Float(1.5),
+        ]]);
+        fs::create_dir_all(&output_dir).unwrap();
+        ["BTCUSD-1m.csv", "BTCUSD-1m.ndjson", "BTCUSD-1m.parquet"]
+            .iter()
+            .for_each(|file| fs::write(Path::new(&output_dir).join(file), "existing").unwrap());
+
+        let existing: Vec<Vec<String>> = ["append", "overwrite"]
+            .iter()
+            .map(|if_exists| {
+                let formats: &[&str] = match *if_exists {
+                    "append" => &["csv", "json"],
+                    _ => &["csv", "json", "parquet"],
+                };
+                let settings: DownloadSettings = DownloadSettings {
+                    formats: formats.iter().map(|format| format.to_string()).collect(),
+                    files: FileNameSettings {
+                        output_dir: Some(output_dir.clone()),
+                        template: Some("{symbol}{base}-{interval}".to_string()),
+                        if_exists: if_exists.to_string(),
+                    },
+                    ..DownloadSettings::default()
+                };
+                let mut sinks: Vec<Box<dyn Sink>> = open_sinks(&series, &values, &settings);
+                sinks.iter_mut().for_each(|sink| sink.write_page(&page));
+                sinks.into_iter().for_each(|sink| sink.discard());
+                let mut files: Vec<String> = fs::read_dir(&output_dir)
+                    .unwrap()
+                    .map(|entry| {
+                        let path = entry.unwrap().path();
+                        let contents: String = fs::read_to_string(&path).unwrap();
+                        format!("{}: {contents}", path.file_name().unwrap().to_string_lossy())
+                    })
+                    .collect();
+                files.sort();
+                files
+            })
+            .collect();
+        fs::remove_dir_all(&output_dir).unwrap();
+
+        let untouched: Vec<String> = vec![
+            "BTCUSD-1m.csv: existing".to_string(),
+            "BTCUSD-1m.ndjson: existing".to_string(),
+            "BTCUSD-1m.parquet: existing".to_string(),
+        ];
+        assert_eq!(existing, vec![untouched.clone(), untouched]);
+    }
+}
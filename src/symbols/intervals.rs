@@ -1,5 +1,49 @@
 //! Time intervals in Bitfinex.
 
+use chrono::{Months, TimeZone, Utc};
+
 /// "1m", "5m", "15m", "30m", "1h", "3h", "6h", "12h", "1D", "7D", "14D", "1M"
 pub const INTERVALS: [&str; 12] =
     ["1m", "5m", "15m", "30m", "1h", "3h", "6h", "12h", "1D", "7D", "14D", "1M"];
+
+/// Length of an interval in milliseconds.
+/// ```text
+/// interval: &str: One of INTERVALS.
+///
+/// Returns: Option<i64>: None for "1M", as months have no fixed length.
+/// ```
+pub fn interval_to_millis(interval: &str) -> Option<i64> {
+    const MINUTE: i64 = 60_000;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+    match interval {
+        "1m" => Some(MINUTE),
+        "5m" => Some(5 * MINUTE),
+        "15m" => Some(15 * MINUTE),
+        "30m" => Some(30 * MINUTE),
+        "1h" => Some(HOUR),
+        "3h" => Some(3 * HOUR),
+        "6h" => Some(6 * HOUR),
+        "12h" => Some(12 * HOUR),
+        "1D" => Some(DAY),
+        "7D" => Some(7 * DAY),
+        "14D" => Some(14 * DAY),
+        _ => None,
+    }
+}
+
+/// Timestamp of the candle following the given one.
+/// ```text
+/// timestamp: i64: Time in ms on which the candle opens.
+/// interval: &str: One of INTERVALS.
+/// ```
+pub fn next_timestamp(timestamp: i64, interval: &str) -> Option<i64> {
+    match interval_to_millis(interval) {
+        Some(step) => Some(timestamp + step),
+        None => Utc
+            .timestamp_millis_opt(timestamp)
+            .single()?
+            .checked_add_months(Months::new(1))
+            .map(|datetime| datetime.timestamp_millis()),
+    }
+}
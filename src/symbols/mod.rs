@@ -2,3 +2,15 @@
 
 pub mod intervals;
 pub mod list_of_currency;
+
+/// Ticker stored for a pair, always uppercase so `btc usd` and `BTC USD` are the same series.
+/// ```text
+/// Args:
+/// symbol: &str: Cryptocurrency symbol, ie. btc.
+/// base_currency: &str: Base currency, ie. usd.
+///
+/// Returns: String: ie. BTCUSD.
+/// ```
+pub fn ticker(symbol: &str, base_currency: &str) -> String {
+    format!("{}{}", symbol, base_currency).to_uppercase()
+}
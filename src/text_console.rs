@@ -1,18 +1,33 @@
-//! Print a table to the console.
+//! Print the download progress to the console.
+
+use std::{
+    collections::VecDeque,
+    io::{self, IsTerminal},
+    time::{Duration, Instant},
+};
 
 use chrono::{TimeZone, Utc};
 use comfy_table::{
     presets::{ASCII_NO_BORDERS, UTF8_FULL},
     Attribute, Cell, CellAlignment, Color, ContentArrangement, Table,
 };
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde_json::json;
 
-use crate::exchanges::bitfinex::{CandleData, FloatOrInt};
+use crate::{
+    database::DownloadRecord,
+    exchanges::bitfinex::{Candle, CandleData, FloatOrInt},
+    output,
+};
+
+/// Number of candles shown above the progress bar.
+const PREVIEW_CANDLES: usize = 5;
 
 /// Table format to be displayed while data is downloaded.
 pub fn setup_table() -> Table {
     let mut table: Table = Table::new();
     const TABLE_COLUMNS: [&str; 8] =
-        ["Open", "High", "Low", "Close", "Volume", "Timestamp", "Ticker", "Time"];
+        ["Open", "High", "Low", "Close", "Volume", "Ticker", "Interval", "Time"];
     table
         .load_preset(UTF8_FULL)
         .set_header(TABLE_COLUMNS.iter().map(|column| -> Cell {
@@ -27,29 +42,228 @@ pub fn setup_table() -> Table {
     table
 }
 
-/// Live data from the candles.
-pub fn write_to_column(
-    ticker: &str,
-    interval: &str,
-    data_downloaded: Vec<CandleData>,
-    mut table: Table,
-) {
-    data_downloaded.into_iter().for_each(|candle_data: CandleData| {
-        candle_data.0.into_iter().for_each(|single_candle_info| {
-            let datetime: String = Utc
-                .timestamp_millis(
-                    single_candle_info[0]
-                        .to_string()
-                        .parse()
-                        .expect("Datetime could not be converted from timestamp"),
-                )
-                .to_string();
-            insert_rows_to_table(single_candle_info, &mut table, ticker, interval, datetime);
+/// Table with the candles read from a database.
+pub fn candles_table(series: &[(String, String, Vec<Candle>)]) -> Table {
+    let mut table: Table = setup_table();
+    series.iter().for_each(|(ticker, interval, candles)| {
+        candles.iter().for_each(|candle| {
+            let candle_data: [FloatOrInt; 6] = candle.into();
+            insert_rows_to_table(
+                candle_data,
+                &mut table,
+                ticker,
+                interval,
+                to_datetime(candle_data[0]),
+            );
         });
     });
-    // clear the console
-    print!("{esc}c", esc = 27 as char);
-    println!("{table}");
+    table
+}
+
+/// Table with the download history of a database.
+pub fn history_table(downloads: &[DownloadRecord]) -> Table {
+    let mut table: Table = Table::new();
+    const TABLE_COLUMNS: [&str; 10] = [
+        "Run",
+        "Version",
+        "Exchange",
+        "Ticker",
+        "Interval",
+        "Requested",
+        "Downloaded",
+        "Requests",
+        "Candles",
+        "Gaps",
+    ];
+    table
+        .load_preset(UTF8_FULL)
+        .set_header(TABLE_COLUMNS.iter().map(|column| -> Cell {
+            Cell::new(column).add_attribute(Attribute::Bold).fg(Color::Green)
+        }))
+        .set_content_arrangement(ContentArrangement::Dynamic);
+    downloads.iter().for_each(|download| {
+        let range = |start: Option<i64>, end: Option<i64>| -> String {
+            match (start, end) {
+                (Some(start), Some(end)) => format!(
+                    "{} - {}",
+                    to_datetime(FloatOrInt::Int(start)),
+                    to_datetime(FloatOrInt::Int(end))
+                ),
+                _ => "-".to_string(),
+            }
+        };
+        table.add_row(vec![
+            Cell::new(to_datetime(FloatOrInt::Int(download.run_timestamp))),
+            Cell::new(&download.tool_version),
+            Cell::new(format!("{}\n{}", download.exchange, download.endpoint)),
+            Cell::new(&download.ticker),
+            Cell::new(&download.interval),
+            Cell::new(range(Some(download.requested_start), Some(download.requested_end))),
+            Cell::new(range(download.actual_start, download.actual_end)),
+            Cell::new(download.requests).set_alignment(CellAlignment::Right),
+            Cell::new(download.candles).set_alignment(CellAlignment::Right),
+            Cell::new(download.gaps).set_alignment(CellAlignment::Right),
+        ]);
+    });
+    table
+}
+
+/// Progress of a download.
+/// ```text
+/// On a terminal a progress bar is drawn with the last candles above it,
+/// otherwise a plain line is printed every time the percentage changes.
+/// In JSON mode an event is emitted for every request.
+/// ```
+pub struct Progress {
+    ticker: String,
+    interval: String,
+    bar: Option<ProgressBar>,
+    total_requests: u64,
+    requests: u64,
+    candles: usize,
+    last_percent: u64,
+    started: Instant,
+    preview: VecDeque<[FloatOrInt; 6]>,
+}
+
+impl Progress {
+    /// Starts displaying the progress.
+    /// ```text
+    /// Args:
+    /// ticker: &str: Quote + base asset.
+    /// interval: &str: Time interval of the data.
+    /// total_requests: u64: Number of requests needed for the download.
+    /// ```
+    pub fn new(ticker: &str, interval: &str, total_requests: u64) -> Self {
+        let draw_bar: bool =
+            io::stdout().is_terminal() && !output::is_json() && !output::is_stdout_reserved();
+        let bar: Option<ProgressBar> = draw_bar.then(|| {
+            let bar: ProgressBar =
+                ProgressBar::with_draw_target(Some(total_requests), ProgressDrawTarget::stdout());
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{msg}\n{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {percent}% \
+                     {pos}/{len} requests ({per_sec}) {prefix} candles, ETA {eta}",
+                )
+                .expect("Invalid progress bar template")
+                .progress_chars("#>-"),
+            );
+            bar.set_prefix("0");
+            bar.enable_steady_tick(Duration::from_millis(250));
+            bar
+        });
+        Self {
+            ticker: ticker.to_string(),
+            interval: interval.to_string(),
+            bar,
+            total_requests: total_requests.max(1),
+            requests: 0,
+            candles: 0,
+            last_percent: 0,
+            started: Instant::now(),
+            preview: VecDeque::with_capacity(PREVIEW_CANDLES),
+        }
+    }
+
+    /// Records a downloaded page.
+    pub fn update(&mut self, page: &CandleData) {
+        self.requests += 1;
+        self.candles += page.0.len();
+        page.0.iter().for_each(|candle| {
+            if self.preview.len() == PREVIEW_CANDLES {
+                self.preview.pop_front();
+            }
+            self.preview.push_back(*candle);
+        });
+
+        match &self.bar {
+            Some(bar) => {
+                bar.set_prefix(self.candles.to_string());
+                bar.set_message(self.preview_table().to_string());
+                bar.inc(1);
+            }
+            None if output::is_json() => {
+                let (requests_per_second, eta) = self.rate();
+                output::event(
+                    "progress",
+                    &json!({
+                        "ticker": self.ticker,
+                        "interval": self.interval,
+                        "requests": self.requests,
+                        "total_requests": self.total_requests,
+                        "percent": self.requests * 100 / self.total_requests,
+                        "candles": self.candles,
+                        "requests_per_second": requests_per_second,
+                        "eta_seconds": eta,
+                        "last_timestamp": self.preview.back().map(|candle| candle[0].as_i64()),
+                    }),
+                );
+            }
+            None => {
+                let percent: u64 = self.requests * 100 / self.total_requests;
+                if percent > self.last_percent || self.requests == self.total_requests {
+                    self.last_percent = percent;
+                    output::print_line(&self.plain_line(percent));
+                }
+            }
+        }
+    }
+
+    /// Stops the progress bar, leaving the last state on screen.
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish();
+        }
+    }
+
+    /// Requests per second and estimated seconds left.
+    fn rate(&self) -> (f64, f64) {
+        let elapsed: f64 = self.started.elapsed().as_secs_f64();
+        let requests_per_second: f64 =
+            if elapsed > 0.0 { self.requests as f64 / elapsed } else { 0.0 };
+        let remaining: u64 = self.total_requests.saturating_sub(self.requests);
+        let eta: f64 =
+            if requests_per_second > 0.0 { remaining as f64 / requests_per_second } else { 0.0 };
+        (requests_per_second, eta)
+    }
+
+    /// Progress as a single line of text.
+    fn plain_line(&self, percent: u64) -> String {
+        let (requests_per_second, eta) = self.rate();
+        let last_candle: String = self
+            .preview
+            .back()
+            .map(|candle| format!(", last candle {}", to_datetime(candle[0])))
+            .unwrap_or_default();
+        format!(
+            "{} {}: {percent}% {}/{} requests ({requests_per_second:.2}/s) {} candles, ETA \
+             {eta:.0}s{last_candle}",
+            self.ticker, self.interval, self.requests, self.total_requests, self.candles,
+        )
+    }
+
+    /// Table with the last candles downloaded.
+    fn preview_table(&self) -> Table {
+        let mut table: Table = setup_table();
+        self.preview.iter().for_each(|candle| {
+            insert_rows_to_table(
+                *candle,
+                &mut table,
+                &self.ticker,
+                &self.interval,
+                to_datetime(candle[0]),
+            );
+        });
+        table
+    }
+}
+
+/// Converts the timestamp of a candle to a readable date.
+fn to_datetime(timestamp: FloatOrInt) -> String {
+    Utc.timestamp_millis_opt(timestamp.as_i64())
+        .single()
+        .expect("Datetime could not be converted from timestamp")
+        .to_string()
 }
 
 fn insert_rows_to_table(
@@ -60,10 +274,10 @@ fn insert_rows_to_table(
     datetime: String,
 ) {
     table.add_row(vec![
-        Cell::new(single_candle_info[2]).set_alignment(CellAlignment::Center),
         Cell::new(single_candle_info[1]).set_alignment(CellAlignment::Center),
         Cell::new(single_candle_info[3]).set_alignment(CellAlignment::Center),
         Cell::new(single_candle_info[4]).set_alignment(CellAlignment::Center),
+        Cell::new(single_candle_info[2]).set_alignment(CellAlignment::Center),
         Cell::new(single_candle_info[5]).set_alignment(CellAlignment::Center),
         Cell::new(ticker).set_alignment(CellAlignment::Center),
         Cell::new(interval).set_alignment(CellAlignment::Center),
@@ -0,0 +1,316 @@
+//! OHLCV integrity checks for downloaded or stored candles.
+use std::fs;
+
+use chrono::{Datelike, TimeZone, Timelike, Utc};
+use serde::Serialize;
+
+use crate::{
+    database::read_candlesticks,
+    exchanges::bitfinex::Candle,
+    output::{self, fail, success, warning},
+    symbols::intervals::{self, next_timestamp},
+};
+
+/// What to do with the validation report of a download.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationSettings {
+    /// File where the JSON report is written, if any.
+    pub report_file: Option<String>,
+    /// Abort the run when the data has any issue.
+    pub strict: bool,
+}
+
+/// Kind of problem found in a candle.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// A price is zero, negative or not a number.
+    NonPositivePrice,
+    /// Open or close are not between low and high.
+    PriceOutsideRange,
+    /// Volume is below zero.
+    NegativeVolume,
+    /// The timestamp is not on the interval grid.
+    Misaligned,
+    /// The timestamp is lower than the previous candle.
+    OutOfOrder,
+    /// The timestamp is the same as the previous candle.
+    Duplicate,
+}
+
+/// A single problem found in a candle.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub timestamp: i64,
+    pub kind: IssueKind,
+    pub detail: String,
+}
+
+/// Result of validating a series of candles.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ValidationReport {
+    pub ticker: String,
+    pub interval: String,
+    pub candles: usize,
+    pub first_timestamp: Option<i64>,
+    pub last_timestamp: Option<i64>,
+    /// Holes in the series, Bitfinex skips periods without trades so these are not issues.
+    pub gaps: usize,
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Checks that the candle is on the interval grid.
+/// ```text
+/// Weekly candles are only required to start at midnight and monthly
+/// candles at midnight of the first day of the month.
+/// ```
+fn is_aligned(timestamp: i64, interval: &str) -> bool {
+    const DAY: i64 = 86_400_000;
+    match (interval, intervals::interval_to_millis(interval)) {
+        ("7D" | "14D", _) => timestamp % DAY == 0,
+        (_, Some(step)) => timestamp % step == 0,
+        (_, None) => match Utc.timestamp_millis_opt(timestamp).single() {
+            Some(datetime) => {
+                datetime.day() == 1
+                    && datetime.num_seconds_from_midnight() == 0
+                    && datetime.timestamp_subsec_millis() == 0
+            }
+            None => false,
+        },
+    }
+}
+
+/// Checks the prices and volume of a single candle.
+fn check_values(candle: &Candle, issues: &mut Vec<Issue>) {
+    let mut push = |kind: IssueKind, detail: String| {
+        issues.push(Issue { timestamp: candle.timestamp, kind, detail })
+    };
+    let prices: [(&str, f64); 4] = [
+        ("open", candle.open),
+        ("high", candle.high),
+        ("low", candle.low),
+        ("close", candle.close),
+    ];
+    prices.iter().filter(|(_, price)| price.is_nan() || *price <= 0.0).for_each(|(name, price)| {
+        push(IssueKind::NonPositivePrice, format!("{name} is {price}"));
+    });
+    if candle.low > candle.high {
+        push(IssueKind::PriceOutsideRange, format!("low {} > high {}", candle.low, candle.high));
+    }
+    [("open", candle.open), ("close", candle.close)]
+        .iter()
+        .filter(|(_, price)| *price < candle.low || *price > candle.high)
+        .for_each(|(name, price)| {
+            push(
+                IssueKind::PriceOutsideRange,
+                format!("{name} {price} outside low {} / high {}", candle.low, candle.high),
+            );
+        });
+    if candle.volume < 0.0 || candle.volume.is_nan() {
+        push(IssueKind::NegativeVolume, format!("volume is {}", candle.volume));
+    }
+}
+
+/// Validates a series of candles.
+/// ```text
+/// Args:
+/// ticker: &str: Quote + base asset.
+/// interval: &str: Time interval of the candles.
+/// candles: &[Candle]: Candles in the order they were downloaded or stored.
+///
+/// Returns:
+/// ValidationReport: Every issue found in the series.
+/// ```
+pub fn validate_candles(ticker: &str, interval: &str, candles: &[Candle]) -> ValidationReport {
+    let mut issues: Vec<Issue> = Vec::new();
+    let mut gaps: usize = 0;
+    let mut previous: Option<i64> = None;
+
+    candles.iter().for_each(|candle| {
+        check_values(candle, &mut issues);
+        if !is_aligned(candle.timestamp, interval) {
+            issues.push(Issue {
+                timestamp: candle.timestamp,
+                kind: IssueKind::Misaligned,
+                detail: format!("not aligned to the {interval} grid"),
+            });
+        }
+        match previous {
+            Some(last) if candle.timestamp == last => issues.push(Issue {
+                timestamp: candle.timestamp,
+                kind: IssueKind::Duplicate,
+                detail: "same timestamp as the previous candle".to_string(),
+            }),
+            Some(last) if candle.timestamp < last => issues.push(Issue {
+                timestamp: candle.timestamp,
+                kind: IssueKind::OutOfOrder,
+                detail: format!("previous candle is {last}"),
+            }),
+            Some(last)
+                if next_timestamp(last, interval).is_some_and(|next| candle.timestamp > next) =>
+            {
+                gaps += 1
+            }
+            _ => {}
+        }
+        previous = Some(previous.map_or(candle.timestamp, |last| last.max(candle.timestamp)));
+    });
+
+    ValidationReport {
+        ticker: ticker.to_string(),
+        interval: interval.to_string(),
+        candles: candles.len(),
+        first_timestamp: candles.first().map(|candle| candle.timestamp),
+        last_timestamp: candles.last().map(|candle| candle.timestamp),
+        gaps,
+        issues,
+    }
+}
+
+/// Serializes the reports as JSON.
+fn to_json<T: Serialize + ?Sized>(reports: &T) -> String {
+    serde_json::to_string_pretty(reports)
+        .unwrap_or_else(|_| fail("Could not serialize the validation report"))
+}
+
+/// Prints a summary of the report and applies the settings.
+/// ```text
+/// Args:
+/// report: &ValidationReport: Report of the downloaded data.
+/// settings: &ValidationSettings: Where to write the report and whether to fail.
+/// ```
+pub fn handle_report(report: &ValidationReport, settings: &ValidationSettings) {
+    if report.is_valid() {
+        success(&format!("Validation passed for {} candles ✅", report.candles));
+    } else {
+        warning(&format!(
+            "Validation found {} issues in {} candles",
+            report.issues.len(),
+            report.candles
+        ));
+    }
+    output::event("validation", report);
+    if let Some(report_file) = &settings.report_file {
+        fs::write(report_file, to_json(report))
+            .unwrap_or_else(|_| fail("Could not write the validation report"));
+    }
+    if settings.strict && !report.is_valid() {
+        fail("Data failed validation ❌, nothing was written")
+    }
+}
+
+/// Validates every ticker and interval stored in an existing database.
+/// ```text
+/// Args:
+/// data_base_file: &str: Path to the .sqlite file.
+///
+/// Returns:
+/// Vec<ValidationReport>: One report per ticker and interval.
+/// ```
+pub fn verify_database(data_base_file: &str) -> Vec<ValidationReport> {
+    read_candlesticks(data_base_file)
+        .into_iter()
+        .map(|(ticker, interval, candles)| validate_candles(&ticker, &interval, &candles))
+        .collect()
+}
+
+/// Runs the `verify` command, the JSON report is printed to stdout.
+/// ```text
+/// Args:
+/// data_base_file: &str: Path to the .sqlite file.
+/// report_file: Option<&str>: Also write the report to this file.
+///
+/// Returns:
+/// bool: Whether every series passed validation.
+/// ```
+pub fn run_verify(data_base_file: &str, report_file: Option<&str>) -> bool {
+    let reports: Vec<ValidationReport> = verify_database(data_base_file);
+    let json: String = to_json(&reports);
+    if output::is_json() {
+        reports.iter().for_each(|report| output::event("validation", report));
+    } else {
+        println!("{json}");
+    }
+    if let Some(report_file) = report_file {
+        fs::write(report_file, json)
+            .unwrap_or_else(|_| fail("Could not write the validation report"));
+    }
+    reports.iter().all(ValidationReport::is_valid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candle(timestamp: i64, open: f64, close: f64, high: f64, low: f64, volume: f64) -> Candle {
+        Candle { timestamp, open, close, high, low, volume }
+    }
+
+    fn kinds(report: &ValidationReport) -> Vec<IssueKind> {
+        report.issues.iter().map(|issue| issue.kind).collect()
+    }
+
+    #[test]
+    fn valid_series_has_no_issues() {
+        let candles: Vec<Candle> = vec![
+            candle(1_609_459_200_000, 10.0, 11.0, 12.0, 9.0, 1.0),
+            candle(1_609_459_260_000, 11.0, 10.5, 11.5, 10.0, 0.0),
+        ];
+        let report: ValidationReport = validate_candles("BTCUSD", "1m", &candles);
+        assert!(report.is_valid());
+        assert_eq!(report.gaps, 0);
+        assert_eq!(report.candles, 2);
+    }
+
+    #[test]
+    fn detects_price_and_volume_issues() {
+        let candles: Vec<Candle> = vec![
+            candle(1_609_459_200_000, 10.0, 13.0, 12.0, 9.0, 1.0),
+            candle(1_609_459_260_000, 0.0, 10.0, 11.0, 0.0, -1.0),
+        ];
+        let report: ValidationReport = validate_candles("BTCUSD", "1m", &candles);
+        assert_eq!(
+            kinds(&report),
+            vec![
+                IssueKind::PriceOutsideRange,
+                IssueKind::NonPositivePrice,
+                IssueKind::NonPositivePrice,
+                IssueKind::NegativeVolume,
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_timestamp_issues() {
+        let candles: Vec<Candle> = vec![
+            candle(1_609_459_200_000, 10.0, 10.0, 10.0, 10.0, 1.0),
+            candle(1_609_459_200_000, 10.0, 10.0, 10.0, 10.0, 1.0),
+            candle(1_609_459_140_000, 10.0, 10.0, 10.0, 10.0, 1.0),
+            candle(1_609_459_500_001, 10.0, 10.0, 10.0, 10.0, 1.0),
+        ];
+        let report: ValidationReport = validate_candles("BTCUSD", "1m", &candles);
+        assert_eq!(
+            kinds(&report),
+            vec![IssueKind::Duplicate, IssueKind::OutOfOrder, IssueKind::Misaligned]
+        );
+        assert_eq!(report.gaps, 1);
+    }
+
+    #[test]
+    fn monthly_candles_follow_calendar() {
+        let candles: Vec<Candle> = vec![
+            candle(1_609_459_200_000, 10.0, 10.0, 10.0, 10.0, 1.0), // 2021-01-01
+            candle(1_612_137_600_000, 10.0, 10.0, 10.0, 10.0, 1.0), // 2021-02-01
+            candle(1_617_235_200_000, 10.0, 10.0, 10.0, 10.0, 1.0), // 2021-04-01
+        ];
+        let report: ValidationReport = validate_candles("BTCUSD", "1M", &candles);
+        assert!(report.is_valid());
+        assert_eq!(report.gaps, 1);
+    }
+}
@@ -0,0 +1,238 @@
+//! Keeps a database up to date with newly closed candles.
+use std::{thread, time};
+
+use chrono::{TimeZone, Utc};
+use serde_json::json;
+use time::Duration;
+
+use crate::{
+    database::{
+        insert_candlesticks, record_download, update_download, DatabaseSettings, DownloadRecord,
+    },
+    exchanges::{
+        bitfinex::{CandleData, FloatOrInt},
+        Exchange,
+    },
+    get_data::{get_candles, STEP_SIZE},
+    output::{self, info, success, warning},
+    postgresql::insert_candlesticks_postgres,
+    symbols::{
+        self,
+        intervals::{interval_to_millis, next_timestamp},
+    },
+    validation::validate_candles,
+};
+
+/// Polls at least once a day, monthly and weekly candles do not follow the epoch grid.
+const MAX_POLL_STEP: i64 = 86400000;
+/// Gives Bitfinex time to publish the candle that just closed.
+const GRACE_PERIOD: i64 = 5000;
+
+/// Whether the candle that opens at the given timestamp is complete.
+fn is_closed(timestamp: i64, interval: &str, now: i64) -> bool {
+    next_timestamp(timestamp, interval).is_some_and(|close_time| close_time <= now)
+}
+
+/// Time in ms of the next poll.
+/// ```text
+/// Args:
+/// now: i64: Current time in ms.
+/// interval: &str: Interval being watched.
+///
+/// Returns:
+/// i64: The next interval boundary plus a grace period.
+/// ```
+fn next_poll(now: i64, interval: &str) -> i64 {
+    let poll_step: i64 = interval_to_millis(interval).unwrap_or(MAX_POLL_STEP).min(MAX_POLL_STEP);
+    now - now.rem_euclid(poll_step) + poll_step + GRACE_PERIOD
+}
+
+/// Keeps only the closed candles newer than the last one stored.
+/// ```text
+/// Args:
+/// candle_data: Vec<CandleData>: Pages downloaded from Bitfinex.
+/// interval: &str: Interval being watched.
+/// last_timestamp: Option<i64>: Newest candle already in the database.
+/// now: i64: Current time in ms.
+///
+/// Returns:
+/// CandleData: Candles that can be appended.
+/// ```
+fn closed_candles(
+    candle_data: Vec<CandleData>,
+    interval: &str,
+    last_timestamp: Option<i64>,
+    now: i64,
+) -> CandleData {
+    let mut candles: Vec<[FloatOrInt; 6]> = candle_data
+        .into_iter()
+        .flat_map(|page| page.0)
+        .filter(|candle| {
+            let timestamp: i64 = candle[0].as_i64();
+            last_timestamp.is_none_or(|last| timestamp > last)
+                && is_closed(timestamp, interval, now)
+        })
+        .collect();
+    candles.sort_by_key(|candle| candle[0].as_i64());
+    candles.dedup_by_key(|candle| candle[0].as_i64());
+    CandleData(candles)
+}
+
+/// Appends the candles and returns the newest timestamp stored.
+fn append(
+    candles: CandleData,
+    exchange: &str,
+    ticker: &str,
+    interval: &str,
+    last: Option<i64>,
+    database_settings: &DatabaseSettings,
+) -> Option<i64> {
+    let newest: Option<i64> = candles.0.last().map(|candle| candle[0].as_i64());
+    let appended: usize = candles.0.len();
+    if let Some(timestamp) = newest {
+        let candles: [CandleData; 1] = [candles];
+        insert_candlesticks(&candles, ticker, interval, database_settings);
+        if let Some(postgres_settings) = &database_settings.postgres {
+            insert_candlesticks_postgres(&candles, exchange, ticker, interval, postgres_settings);
+        }
+        let datetime: String = Utc
+            .timestamp_millis_opt(timestamp)
+            .single()
+            .expect("Datetime could not be converted from timestamp")
+            .to_string();
+        success(&format!("Appended {appended} candles up to {datetime}"));
+        output::event(
+            "appended",
+            &json!({ "ticker": ticker, "interval": interval, "candles": appended, "last_timestamp": timestamp }),
+        );
+    }
+    newest.or(last)
+}
+
+/// Backfills from the start date and then appends every candle as soon as it closes.
+/// ```text
+/// Args:
+/// exchange: &dyn Exchange: Exchange the candles are downloaded from.
+/// symbol: &str: Cryptocurrency symbol.
+/// base_currency: &str: Base trading pair.
+/// interval: &str: Time interval of the candles.
+/// time_start: i64: Time in ms on which the backfill will start.
+/// database_settings: &DatabaseSettings: How the candles are written.
+/// ```
+pub fn watch(
+    exchange: &dyn Exchange,
+    symbol: &str,
+    base_currency: &str,
+    interval: &str,
+    time_start: i64,
+    database_settings: &DatabaseSettings,
+) {
+    let ticker: String = symbols::ticker(symbol, base_currency);
+
+    let now: i64 = Utc::now().timestamp_millis();
+    let backfill: Vec<CandleData> =
+        get_candles(exchange, &ticker, time_start, now, interval, STEP_SIZE, |_| {});
+    let mut record: DownloadRecord = DownloadRecord {
+        requested_start: time_start,
+        requested_end: now,
+        requests: backfill.len(),
+        ..DownloadRecord::new(
+            exchange.name(),
+            exchange.candles_endpoint(&ticker, interval),
+            &ticker,
+            interval,
+        )
+    };
+    let candles: CandleData = closed_candles(backfill, interval, None, now);
+    record.gaps = validate_candles(&ticker, interval, &candles.candles().collect::<Vec<_>>()).gaps;
+    record.candles = candles.0.len();
+    record.actual_start = candles.0.first().map(|candle| candle[0].as_i64());
+    let mut last_timestamp: Option<i64> =
+        append(candles, exchange.name(), &ticker, interval, None, database_settings);
+    record.actual_end = last_timestamp;
+    let record_id: i64 = record_download(&record, database_settings);
+    info(&format!("Watching {ticker} {interval} candles, press Ctrl+C to stop"));
+
+    loop {
+        let wake_up: i64 = next_poll(Utc::now().timestamp_millis(), interval);
+        let wait: i64 = wake_up - Utc::now().timestamp_millis();
+        thread::sleep(Duration::from_millis(wait.max(0) as u64));
+
+        let now: i64 = Utc::now().timestamp_millis();
+        let start_time: i64 = last_timestamp.map_or(time_start, |last| last + 1);
+        tracing::info!(ticker, interval, start_time, "polling for closed candles");
+        match exchange.get_candles(&ticker, interval, start_time, now) {
+            Some(candle_data) => {
+                let candles: CandleData =
+                    closed_candles(vec![candle_data], interval, last_timestamp, now);
+                if let (Some(last), Some(first)) = (last_timestamp, candles.0.first()) {
+                    if next_timestamp(last, interval).is_some_and(|next| first[0].as_i64() > next) {
+                        record.gaps += 1;
+                    }
+                }
+                record.requests += 1;
+                record.requested_end = now;
+                record.candles += candles.0.len();
+                record.actual_start =
+                    record.actual_start.or(candles.0.first().map(|candle| candle[0].as_i64()));
+                last_timestamp = append(
+                    candles,
+                    exchange.name(),
+                    &ticker,
+                    interval,
+                    last_timestamp,
+                    database_settings,
+                );
+                record.actual_end = last_timestamp;
+                update_download(record_id, &record, database_settings);
+            }
+            None => warning(&format!(
+                "Could not reach {}, the candles will be fetched on the next poll",
+                exchange.name()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(timestamps: &[i64]) -> CandleData {
+        CandleData(
+            timestamps
+                .iter()
+                .map(|timestamp| {
+                    [
+                        FloatOrInt::Int(*timestamp),
+                        FloatOrInt::Float(1.0),
+                        FloatOrInt::Float(1.0),
+                        FloatOrInt::Float(1.0),
+                        FloatOrInt::Float(1.0),
+                        FloatOrInt::Float(1.0),
+                    ]
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn polls_at_next_boundary() {
+        assert_eq!(next_poll(90_000, "1m"), 120_000 + GRACE_PERIOD);
+        assert_eq!(next_poll(120_000, "1m"), 180_000 + GRACE_PERIOD);
+        assert_eq!(next_poll(90_000, "7D"), MAX_POLL_STEP + GRACE_PERIOD);
+        assert_eq!(next_poll(90_000, "1M"), MAX_POLL_STEP + GRACE_PERIOD);
+    }
+
+    #[test]
+    fn keeps_only_new_closed_candles() {
+        let candles: CandleData = closed_candles(
+            vec![page(&[180_000, 60_000, 120_000]), page(&[120_000, 240_000])],
+            "1m",
+            Some(60_000),
+            240_000,
+        );
+        let timestamps: Vec<i64> = candles.0.iter().map(|candle| candle[0].as_i64()).collect();
+        assert_eq!(timestamps, vec![120_000, 180_000]);
+    }
+}
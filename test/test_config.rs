@@ -2,4 +2,15 @@
 
 #[cfg(test)]
 #[path = "./test_symbols.rs"]
+#[allow(clippy::bool_assert_comparison)]
 mod test_symbols;
+
+#[cfg(test)]
+#[path = "../src/output.rs"]
+#[allow(dead_code)]
+mod output;
+
+#[cfg(test)]
+#[path = "../src/symbols/mod.rs"]
+#[allow(dead_code)]
+mod symbols;